@@ -0,0 +1,495 @@
+use crate::tool::{is_compiler_driver, is_linker, is_msvc_driver};
+use std::path::Path;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ToolKind {
+    // gcc/clang 等编译器驱动
+    Compiler,
+    // ld/lld 等 GNU 风格的链接器
+    Linker,
+    // cl/clang-cl 等 MSVC 风格的编译器驱动
+    Msvc,
+    // 未知工具按照字符串处理参数
+    Unknown,
+}
+
+impl ToolKind {
+    pub fn detect(command: &str) -> ToolKind {
+        if is_compiler_driver(command) {
+            ToolKind::Compiler
+        } else if is_linker(command) {
+            ToolKind::Linker
+        } else if is_msvc_driver(command) {
+            ToolKind::Msvc
+        } else {
+            ToolKind::Unknown
+        }
+    }
+
+    fn specs(self) -> &'static [OptionSpec] {
+        match self {
+            ToolKind::Compiler => COMPILER_OPTIONS,
+            ToolKind::Linker => LINKER_OPTIONS,
+            ToolKind::Msvc => MSVC_OPTIONS,
+            ToolKind::Unknown => &[],
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Style {
+    // 不带值, 仅用于避免被其它选项的前缀匹配, 如 ld 的 -export-dynamic
+    Flag,
+    // -Xlinker <value>
+    Separate,
+    // /Fo<value>
+    Joined,
+    // -L<value> 或者 -L <value>
+    JoinedOrSeparate,
+    // --sysroot=<value>
+    Equals,
+    // --output=<value> 或者 --output <value>
+    EqualsOrSeparate,
+    // -Wl,<value>,<value>
+    CommaJoined,
+}
+
+struct OptionSpec {
+    name: &'static str,
+    style: Style,
+    // 同一个选项的不同写法使用相同的名称, 如 --library-path 和 -L
+    canonical: &'static str,
+}
+
+const fn spec(name: &'static str, style: Style) -> OptionSpec {
+    OptionSpec {
+        name,
+        style,
+        canonical: name,
+    }
+}
+
+const fn alias(name: &'static str, style: Style, canonical: &'static str) -> OptionSpec {
+    OptionSpec {
+        name,
+        style,
+        canonical,
+    }
+}
+
+const COMPILER_OPTIONS: &[OptionSpec] = &[
+    spec("-o", Style::JoinedOrSeparate),
+    alias("--output", Style::EqualsOrSeparate, "-o"),
+    spec("-L", Style::JoinedOrSeparate),
+    alias("--library-directory", Style::EqualsOrSeparate, "-L"),
+    spec("-l", Style::JoinedOrSeparate),
+    spec("-I", Style::JoinedOrSeparate),
+    alias("--include-directory", Style::EqualsOrSeparate, "-I"),
+    spec("-D", Style::JoinedOrSeparate),
+    alias("--define-macro", Style::EqualsOrSeparate, "-D"),
+    spec("-U", Style::JoinedOrSeparate),
+    spec("-undef", Style::Flag),
+    spec("-x", Style::JoinedOrSeparate),
+    spec("-B", Style::JoinedOrSeparate),
+    spec("-T", Style::JoinedOrSeparate),
+    spec("-u", Style::JoinedOrSeparate),
+    spec("-z", Style::Separate),
+    spec("-MF", Style::JoinedOrSeparate),
+    spec("-MT", Style::JoinedOrSeparate),
+    spec("-MQ", Style::JoinedOrSeparate),
+    spec("-MJ", Style::JoinedOrSeparate),
+    spec("-include", Style::JoinedOrSeparate),
+    spec("-include-pch", Style::Separate),
+    spec("-imacros", Style::JoinedOrSeparate),
+    spec("-isystem", Style::JoinedOrSeparate),
+    spec("-iquote", Style::JoinedOrSeparate),
+    spec("-idirafter", Style::JoinedOrSeparate),
+    spec("-iprefix", Style::JoinedOrSeparate),
+    spec("-iwithprefix", Style::JoinedOrSeparate),
+    spec("-iwithprefixbefore", Style::JoinedOrSeparate),
+    spec("-isysroot", Style::JoinedOrSeparate),
+    spec("-imultilib", Style::JoinedOrSeparate),
+    spec("--sysroot", Style::EqualsOrSeparate),
+    spec("-target", Style::Separate),
+    alias("--target", Style::Equals, "-target"),
+    spec("-arch", Style::Separate),
+    spec("-Xlinker", Style::Separate),
+    spec("-Xassembler", Style::Separate),
+    spec("-Xpreprocessor", Style::Separate),
+    spec("-Xclang", Style::Separate),
+    spec("-Wl,", Style::CommaJoined),
+    spec("-Wa,", Style::CommaJoined),
+    spec("-Wp,", Style::CommaJoined),
+    spec("-aux-info", Style::Separate),
+    spec("-dumpbase", Style::Separate),
+    spec("-dumpdir", Style::Separate),
+    spec("--param", Style::EqualsOrSeparate),
+];
+
+// ld 使用 getopt_long_only 解析参数, 单个 - 的长选项也是合法的, 因此 -e/-h/-u/-y 只支持独立参数值
+const LINKER_OPTIONS: &[OptionSpec] = &[
+    spec("-o", Style::JoinedOrSeparate),
+    alias("--output", Style::EqualsOrSeparate, "-o"),
+    spec("-L", Style::JoinedOrSeparate),
+    alias("--library-path", Style::EqualsOrSeparate, "-L"),
+    spec("-l", Style::JoinedOrSeparate),
+    alias("--library", Style::EqualsOrSeparate, "-l"),
+    spec("-rpath", Style::EqualsOrSeparate),
+    alias("--rpath", Style::EqualsOrSeparate, "-rpath"),
+    spec("-rpath-link", Style::EqualsOrSeparate),
+    alias("--rpath-link", Style::EqualsOrSeparate, "-rpath-link"),
+    spec("-R", Style::JoinedOrSeparate),
+    spec("-T", Style::JoinedOrSeparate),
+    alias("--script", Style::EqualsOrSeparate, "-T"),
+    spec("-m", Style::JoinedOrSeparate),
+    spec("-mllvm", Style::Separate),
+    spec("-z", Style::JoinedOrSeparate),
+    spec("-e", Style::Separate),
+    alias("--entry", Style::EqualsOrSeparate, "-e"),
+    spec("-u", Style::Separate),
+    alias("--undefined", Style::EqualsOrSeparate, "-u"),
+    spec("-y", Style::Separate),
+    alias("--trace-symbol", Style::EqualsOrSeparate, "-y"),
+    spec("-h", Style::Separate),
+    alias("-soname", Style::EqualsOrSeparate, "-h"),
+    alias("--soname", Style::EqualsOrSeparate, "-h"),
+    spec("--sysroot", Style::Equals),
+    spec("--dynamic-linker", Style::EqualsOrSeparate),
+    spec("--version-script", Style::EqualsOrSeparate),
+    spec("-Map", Style::EqualsOrSeparate),
+    spec("-plugin", Style::EqualsOrSeparate),
+    spec("-plugin-opt", Style::EqualsOrSeparate),
+    spec("-export-dynamic", Style::Flag),
+    spec("-eh-frame-hdr", Style::Flag),
+    spec("-omagic", Style::Flag),
+];
+
+// cl 的选项可以使用 / 或者 - 开头, /Fo: 和 /Fe: 的写法支持独立的参数值
+const MSVC_OPTIONS: &[OptionSpec] = &[
+    spec("/Fo", Style::Joined),
+    alias("-Fo", Style::Joined, "/Fo"),
+    alias("/Fo:", Style::JoinedOrSeparate, "/Fo"),
+    alias("-Fo:", Style::JoinedOrSeparate, "/Fo"),
+    spec("/Fe", Style::Joined),
+    alias("-Fe", Style::Joined, "/Fe"),
+    alias("/Fe:", Style::JoinedOrSeparate, "/Fe"),
+    alias("-Fe:", Style::JoinedOrSeparate, "/Fe"),
+    spec("/c", Style::Flag),
+    alias("-c", Style::Flag, "/c"),
+    spec("/I", Style::JoinedOrSeparate),
+    alias("-I", Style::JoinedOrSeparate, "/I"),
+    spec("/D", Style::JoinedOrSeparate),
+    alias("-D", Style::JoinedOrSeparate, "/D"),
+    spec("/U", Style::JoinedOrSeparate),
+    alias("-U", Style::JoinedOrSeparate, "/U"),
+    spec("/FI", Style::JoinedOrSeparate),
+    alias("-FI", Style::JoinedOrSeparate, "/FI"),
+    spec("/link", Style::Flag),
+    alias("-link", Style::Flag, "/link"),
+];
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Argument {
+    // 规范化的选项名称, None 表示输入文件或者未知参数
+    pub option: Option<&'static str>,
+    pub value: Option<String>,
+    // 在参数列表中的起始位置和占用的参数个数
+    pub index: usize,
+    pub count: usize,
+}
+
+impl Argument {
+    pub fn is(&self, option: &str) -> bool {
+        self.option == Some(option)
+    }
+
+    pub fn matches(&self, option: &str, value: &str) -> bool {
+        self.is(option) && self.value.as_deref() == Some(value)
+    }
+
+    pub fn tokens<'a>(&self, args: &'a [String]) -> &'a [String] {
+        &args[self.index..self.index + self.count]
+    }
+}
+
+fn find_spec(kind: ToolKind, arg: &str) -> Option<(&'static OptionSpec, Option<String>, bool)> {
+    // 完全匹配优先, 否则使用最长的前缀匹配
+    if let Some(spec) = kind.specs().iter().find(|s| s.name == arg) {
+        let separate = matches!(
+            spec.style,
+            Style::Separate | Style::JoinedOrSeparate | Style::EqualsOrSeparate
+        );
+        return Some((spec, None, separate));
+    }
+    kind.specs()
+        .iter()
+        .filter_map(|spec| {
+            let rest = arg.strip_prefix(spec.name)?;
+            let value = match spec.style {
+                Style::Joined | Style::JoinedOrSeparate | Style::CommaJoined => rest,
+                Style::Equals | Style::EqualsOrSeparate => rest.strip_prefix('=')?,
+                Style::Flag | Style::Separate => return None,
+            };
+            Some((spec, Some(value.to_string()), false))
+        })
+        .max_by_key(|(spec, _, _)| spec.name.len())
+}
+
+pub fn parse(kind: ToolKind, args: &[String]) -> Vec<Argument> {
+    let mut result = vec![];
+    let mut i = 0;
+    while i < args.len() {
+        let argument = match find_spec(kind, &args[i]) {
+            Some((spec, _, true)) if i + 1 < args.len() => Argument {
+                option: Some(spec.canonical),
+                value: Some(args[i + 1].clone()),
+                index: i,
+                count: 2,
+            },
+            Some((spec, value, false)) => Argument {
+                option: Some(spec.canonical),
+                value,
+                index: i,
+                count: 1,
+            },
+            _ => Argument {
+                option: None,
+                value: None,
+                index: i,
+                count: 1,
+            },
+        };
+        i += argument.count;
+        result.push(argument);
+    }
+    result
+}
+
+// 将规则中的参数解析为单个带值的逻辑选项, 如 -L/opt 或者 "-L /opt"
+pub fn parse_option(kind: ToolKind, text: &str) -> Option<(&'static str, String)> {
+    let tokens: Vec<String> = text.split_whitespace().map(|s| s.to_string()).collect();
+    match parse(kind, &tokens).as_slice() {
+        [Argument {
+            option: Some(option),
+            value: Some(value),
+            ..
+        }] => Some((option, value.clone())),
+        _ => None,
+    }
+}
+
+pub fn find_output(kind: ToolKind, args: &[String]) -> Option<String> {
+    if kind == ToolKind::Msvc {
+        return find_msvc_output(args);
+    }
+    let kind = if kind == ToolKind::Unknown {
+        // 未知工具也尝试按照常见的 -o 写法查找
+        ToolKind::Compiler
+    } else {
+        kind
+    };
+    parse(kind, args)
+        .into_iter()
+        .find(|arg| arg.is("-o"))
+        .and_then(|arg| arg.value)
+}
+
+// -MF 指定的依赖文件, 只有 -MD/-MMD 时 gcc/clang 将输出文件的后缀替换为 .d, 也支持 -Wp,-MD,<file> 的写法
+pub fn find_depfile(args: &[String]) -> Option<String> {
+    let parsed = parse(ToolKind::Compiler, args);
+    let mut depfile = None;
+    let mut generates = false;
+    for arg in parsed.iter() {
+        if arg.is("-MF") {
+            depfile = arg.value.clone();
+        } else if arg.is("-Wp,") {
+            let value = arg.value.as_deref().unwrap_or_default();
+            if let Some(file) = value
+                .strip_prefix("-MD,")
+                .or_else(|| value.strip_prefix("-MMD,"))
+            {
+                depfile = Some(file.to_string());
+            }
+        } else if arg.option.is_none() {
+            generates |= matches!(args[arg.index].as_str(), "-MD" | "-MMD");
+        }
+    }
+    if depfile.is_some() || !generates {
+        return depfile;
+    }
+    let output = find_output(ToolKind::Compiler, args).or_else(|| {
+        // 没有 -o 时使用源文件的名称
+        parsed.iter().find_map(|arg| {
+            let token = &args[arg.index];
+            (arg.option.is_none() && !token.starts_with('-') && !token.starts_with('@')).then(
+                || {
+                    Path::new(token)
+                        .file_name()
+                        .unwrap()
+                        .to_string_lossy()
+                        .into_owned()
+                },
+            )
+        })
+    })?;
+    Some(
+        Path::new(&output)
+            .with_extension("d")
+            .to_string_lossy()
+            .into_owned(),
+    )
+}
+
+// /c 时输出 /Fo 指定的目标文件, 否则输出 /Fe 指定的程序, 没有指定或者指定为目录时使用第一个源文件的名称
+fn find_msvc_output(args: &[String]) -> Option<String> {
+    let parsed = parse(ToolKind::Msvc, args);
+    // /link 之后的参数传给链接器
+    let end = parsed
+        .iter()
+        .position(|arg| arg.is("/link"))
+        .unwrap_or(parsed.len());
+    let parsed = &parsed[..end];
+    let (option, extension) = if parsed.iter().any(|arg| arg.is("/c")) {
+        ("/Fo", "obj")
+    } else {
+        ("/Fe", "exe")
+    };
+    let value = parsed
+        .iter()
+        .rev()
+        .find(|arg| arg.is(option))
+        .and_then(|arg| arg.value.clone())
+        .unwrap_or_default();
+    if !value.is_empty() && !value.ends_with(['/', '\\']) {
+        return Some(value);
+    }
+    let source = parsed.iter().find_map(|arg| {
+        let token = &args[arg.index];
+        let path = Path::new(token);
+        let is_source = arg.option.is_none()
+            && path.extension().is_some_and(|ext| {
+                ["c", "cc", "cpp", "cxx"].contains(&ext.to_string_lossy().to_lowercase().as_str())
+            });
+        is_source.then(|| path.file_stem().unwrap().to_string_lossy().into_owned())
+    })?;
+    Some(format!("{}{}.{}", value, source, extension))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(args: &[&str]) -> Vec<String> {
+        args.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_parse_compiler() {
+        let args = strings(&[
+            "-o",
+            "-lm",
+            "-L/opt",
+            "-L",
+            "/usr/lib",
+            "-Wl,-Bstatic,-lfoo",
+            "a.c",
+            "-MMD",
+            "-MF",
+            "a.d",
+            "--output=b",
+        ]);
+        let parsed = parse(ToolKind::Compiler, &args);
+        assert_eq!(parsed.len(), 8);
+        assert!(parsed[0].matches("-o", "-lm"));
+        assert_eq!(parsed[0].count, 2);
+        assert!(parsed[1].matches("-L", "/opt"));
+        assert!(parsed[2].matches("-L", "/usr/lib"));
+        assert!(parsed[3].matches("-Wl,", "-Bstatic,-lfoo"));
+        assert_eq!(parsed[4].option, None);
+        assert_eq!(parsed[5].option, None);
+        assert!(parsed[6].matches("-MF", "a.d"));
+        assert!(parsed[7].matches("-o", "b"));
+    }
+
+    #[test]
+    fn test_parse_linker() {
+        let args = strings(&[
+            "--library-path=/opt",
+            "-export-dynamic",
+            "-rpath-link",
+            "/lib",
+            "-rpath=/x",
+            "--library",
+            "c",
+        ]);
+        let parsed = parse(ToolKind::Linker, &args);
+        assert!(parsed[0].matches("-L", "/opt"));
+        assert!(parsed[1].is("-export-dynamic"));
+        assert!(parsed[2].matches("-rpath-link", "/lib"));
+        assert!(parsed[3].matches("-rpath", "/x"));
+        assert!(parsed[4].matches("-l", "c"));
+    }
+
+    #[test]
+    fn test_parse_option() {
+        assert_eq!(
+            parse_option(ToolKind::Compiler, "-L/opt"),
+            Some(("-L", "/opt".to_string()))
+        );
+        assert_eq!(
+            parse_option(ToolKind::Linker, "-L /opt"),
+            Some(("-L", "/opt".to_string()))
+        );
+        assert_eq!(parse_option(ToolKind::Compiler, "-static"), None);
+        assert_eq!(parse_option(ToolKind::Unknown, "-L/opt"), None);
+    }
+
+    #[test]
+    fn test_find_output() {
+        let output = |kind, args: &[&str]| find_output(kind, &strings(args));
+        assert_eq!(
+            output(ToolKind::Compiler, &["-c", "a.c", "--output=a.o"]),
+            Some("a.o".to_string())
+        );
+        assert_eq!(
+            output(ToolKind::Unknown, &["-o", "a.out"]),
+            Some("a.out".to_string())
+        );
+        assert_eq!(
+            output(ToolKind::Msvc, &["/c", "/Foout\\a.obj", "a.c"]),
+            Some("out\\a.obj".to_string())
+        );
+        assert_eq!(
+            output(ToolKind::Msvc, &["-c", "/Fo:", "a.obj", "a.c"]),
+            Some("a.obj".to_string())
+        );
+        assert_eq!(
+            output(ToolKind::Msvc, &["/c", "/Foobj/", "src/main.cpp"]),
+            Some("obj/main.obj".to_string())
+        );
+        assert_eq!(
+            output(ToolKind::Msvc, &["/Foa.obj", "main.c", "/link", "/Fex.exe"]),
+            Some("main.exe".to_string())
+        );
+        assert_eq!(output(ToolKind::Msvc, &["/c"]), None);
+    }
+
+    #[test]
+    fn test_find_depfile() {
+        let depfile = |args: &[&str]| find_depfile(&strings(args));
+        assert_eq!(depfile(&["-c", "a.c", "-o", "obj/a.o"]), None);
+        assert_eq!(
+            depfile(&["-c", "a.c", "-MMD", "-o", "obj/a.o"]),
+            Some("obj/a.d".to_string())
+        );
+        assert_eq!(depfile(&["-c", "src/a.c", "-MD"]), Some("a.d".to_string()));
+        assert_eq!(
+            depfile(&["-c", "a.c", "-MD", "-MF", "deps/a.dep", "-o", "a.o"]),
+            Some("deps/a.dep".to_string())
+        );
+        assert_eq!(
+            depfile(&["-c", "a.c", "-Wp,-MMD,.a.o.d", "-o", "a.o"]),
+            Some(".a.o.d".to_string())
+        );
+    }
+}
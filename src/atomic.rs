@@ -0,0 +1,270 @@
+use anyhow::Result;
+use simplelog::{debug, warn};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::argument::{self, ToolKind};
+use crate::{rewrite_arguments, Configuration};
+
+// 命令写入同一目录下的临时文件, 成功后再重命名为真正的输出文件
+pub struct AtomicOutput {
+    target: PathBuf,
+    temp: PathBuf,
+    arguments: Vec<String>,
+    responses: HashMap<String, Vec<String>>,
+}
+
+// 保留原来的文件名和扩展名, cl 的 /Fe 等会根据扩展名补全文件名
+fn temp_path(output: &str) -> String {
+    let path = Path::new(output);
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let temp = format!(".clw-{}-{}", std::process::id(), name);
+    match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => {
+            parent.join(temp).to_string_lossy().into_owned()
+        }
+        _ => temp,
+    }
+}
+
+// -MD/-MMD 默认根据 -o 生成依赖文件的路径和目标, 需要显式指定为真正的输出文件
+fn dependency_arguments(args: &[String], output: &str) -> Vec<String> {
+    let parsed = argument::parse(ToolKind::Compiler, args);
+    let has = |option: &str| parsed.iter().any(|arg| arg.is(option));
+    let generates = parsed
+        .iter()
+        .any(|arg| arg.option.is_none() && matches!(args[arg.index].as_str(), "-MD" | "-MMD"));
+    let mut result = vec![];
+    if !generates {
+        return result;
+    }
+    if !has("-MF") {
+        let depfile = Path::new(output).with_extension("d");
+        result.extend(["-MF".to_string(), depfile.to_string_lossy().into_owned()]);
+    }
+    if !has("-MT") && !has("-MQ") {
+        result.extend(["-MT".to_string(), output.to_string()]);
+    }
+    result
+}
+
+// 将输出参数改为临时文件, 没有显式的输出参数时返回 None
+pub fn prepare(config: &mut Configuration) -> Result<Option<AtomicOutput>> {
+    let output = match config.output_file() {
+        Some(output) => output,
+        None => {
+            warn!("-clw-atomic-output: the command has no output argument");
+            return Ok(None);
+        }
+    };
+    let target = config.resolve_path(&output)?;
+    let temp = temp_path(&output);
+    let atomic = AtomicOutput {
+        target,
+        temp: config.resolve_path(&temp)?,
+        arguments: config.arguments.clone(),
+        responses: config
+            .response_map
+            .iter()
+            .map(|(path, res)| (path.clone(), res.values.clone()))
+            .collect(),
+    };
+
+    let kind = match config.tool_kind() {
+        ToolKind::Unknown => ToolKind::Compiler,
+        kind => kind,
+    };
+    let f = |args: &mut Vec<String>| {
+        rewrite_arguments(kind, args, |arg| {
+            if arg.value.as_deref() != Some(output.as_str()) {
+                return None;
+            }
+            match arg.option {
+                Some("-o") => Some(vec!["-o".to_string(), temp.clone()]),
+                Some(option @ ("/Fo" | "/Fe")) if kind == ToolKind::Msvc => {
+                    Some(vec![format!("{}{}", option, temp)])
+                }
+                _ => None,
+            }
+        })
+    };
+    let mut changed = f(&mut config.arguments);
+    for res in config.response_map.values_mut() {
+        if f(&mut res.values) {
+            res.changed = true;
+            changed = true;
+        }
+    }
+    if !changed {
+        // cl 的 /Fo 指定为目录等情况
+        warn!("-clw-atomic-output: cannot rewrite the output {}", output);
+        return Ok(None);
+    }
+    if kind == ToolKind::Compiler {
+        let dependency = dependency_arguments(&config.expanded_arguments(), &output);
+        config.arguments.extend(dependency);
+    }
+    config.replace_response_file()?;
+    signal::set_temp_file(&atomic.temp);
+    debug!(
+        "writing {} to {}",
+        atomic.target.to_string_lossy(),
+        atomic.temp.to_string_lossy()
+    );
+    Ok(Some(atomic))
+}
+
+impl AtomicOutput {
+    // 收到信号时先转发给命令并等待它退出, 再删除临时文件
+    pub fn watch_child(&self, pid: u32) {
+        signal::set_child(pid);
+    }
+
+    // 恢复原来的参数, 成功时重命名为输出文件, 否则删除临时文件
+    pub fn commit(self, config: &mut Configuration, success: bool) -> Result<()> {
+        config.arguments = self.arguments.clone();
+        for (path, res) in config.response_map.iter_mut() {
+            if let Some(values) = self.responses.get(path) {
+                res.values = values.clone();
+            }
+        }
+        if success {
+            fs::rename(&self.temp, &self.target)?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for AtomicOutput {
+    fn drop(&mut self) {
+        signal::clear_temp_file();
+        fs::remove_file(&self.temp).unwrap_or(());
+    }
+}
+
+// 收到 SIGINT/SIGTERM/SIGHUP 时删除临时文件, 然后按照默认的方式退出
+#[cfg(unix)]
+mod signal {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+    use std::path::Path;
+    use std::ptr;
+    use std::sync::atomic::{AtomicI32, AtomicPtr, Ordering};
+
+    static TEMP_FILE: AtomicPtr<libc::c_char> = AtomicPtr::new(ptr::null_mut());
+    static CHILD: AtomicI32 = AtomicI32::new(0);
+    const SIGNALS: [libc::c_int; 3] = [libc::SIGINT, libc::SIGTERM, libc::SIGHUP];
+
+    extern "C" fn handler(signal: libc::c_int) {
+        let path = TEMP_FILE.load(Ordering::SeqCst);
+        let child = CHILD.load(Ordering::SeqCst);
+        unsafe {
+            // 命令仍然可能写入临时文件, 等待它退出之后再删除
+            if child > 0 {
+                libc::kill(child, signal);
+                while libc::waitpid(child, ptr::null_mut(), 0) == -1
+                    && std::io::Error::last_os_error().raw_os_error() == Some(libc::EINTR)
+                {
+                }
+            }
+            if !path.is_null() {
+                libc::unlink(path);
+            }
+            libc::signal(signal, libc::SIG_DFL);
+            libc::raise(signal);
+        }
+    }
+
+    pub fn set_temp_file(path: &Path) {
+        let path = match CString::new(path.as_os_str().as_bytes()) {
+            Ok(path) => path,
+            Err(_) => return,
+        };
+        TEMP_FILE.store(path.into_raw(), Ordering::SeqCst);
+        for signal in SIGNALS {
+            unsafe {
+                libc::signal(signal, handler as *const () as libc::sighandler_t);
+            }
+        }
+    }
+
+    pub fn set_child(pid: u32) {
+        CHILD.store(pid as libc::pid_t, Ordering::SeqCst);
+    }
+
+    pub fn clear_temp_file() {
+        // 不释放路径, 避免信号处理函数使用已经释放的内存
+        TEMP_FILE.store(ptr::null_mut(), Ordering::SeqCst);
+        CHILD.store(0, Ordering::SeqCst);
+        for signal in SIGNALS {
+            unsafe {
+                libc::signal(signal, libc::SIG_DFL);
+            }
+        }
+    }
+}
+
+#[cfg(not(unix))]
+mod signal {
+    use std::path::Path;
+
+    pub fn set_temp_file(_: &Path) {}
+
+    pub fn set_child(_: u32) {}
+
+    pub fn clear_temp_file() {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    #[test]
+    fn test_atomic_output() {
+        let dir = env::temp_dir().join(format!("clw_atomic_{}", std::process::id()));
+        fs::create_dir_all(dir.join("obj")).unwrap();
+        let mut config = Configuration::new();
+        config.command = "gcc".to_owned();
+        config.work_dir = dir.to_string_lossy().into_owned();
+        config.arguments = ["-c", "a.c", "-MMD", "-oobj/a.o"]
+            .map(String::from)
+            .to_vec();
+        let original = config.arguments.clone();
+
+        let temp = format!("obj/.clw-{}-a.o", std::process::id());
+        let atomic = prepare(&mut config).unwrap().unwrap();
+        assert_eq!(
+            config.arguments,
+            vec!["-c", "a.c", "-MMD", "-o", &temp, "-MF", "obj/a.d", "-MT", "obj/a.o"]
+        );
+        fs::write(dir.join(&temp), "partial").unwrap();
+        atomic.commit(&mut config, false).unwrap();
+        assert_eq!(config.arguments, original);
+        assert!(!dir.join(&temp).exists());
+        assert!(!dir.join("obj/a.o").exists());
+
+        let atomic = prepare(&mut config).unwrap().unwrap();
+        fs::write(dir.join(&temp), "object").unwrap();
+        atomic.commit(&mut config, true).unwrap();
+        assert_eq!(fs::read_to_string(dir.join("obj/a.o")).unwrap(), "object");
+        assert!(!dir.join(&temp).exists());
+
+        config.command = "cl".to_owned();
+        config.arguments = ["/c", "/Foobj/", "a.c"].map(String::from).to_vec();
+        assert!(prepare(&mut config).unwrap().is_none());
+        config.arguments = ["/c", "/Foobj/a.obj", "a.c"].map(String::from).to_vec();
+        let _atomic = prepare(&mut config).unwrap().unwrap();
+        assert_eq!(
+            config.arguments,
+            vec![
+                "/c".to_string(),
+                format!("/Foobj/.clw-{}-a.obj", std::process::id()),
+                "a.c".to_string()
+            ]
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
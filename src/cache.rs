@@ -0,0 +1,522 @@
+use anyhow::{bail, Result};
+use sha2::{Digest, Sha256};
+use std::fs::{self, File, OpenOptions};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use crate::argument::{self, ToolKind};
+use crate::tool::program_identity;
+
+// 缓存格式变化时修改版本, 使旧的缓存全部失效
+const CACHE_VERSION: &str = "clw-cache-2";
+
+pub const DEFAULT_CACHE_MAX_SIZE: u64 = 5 * 1024 * 1024 * 1024;
+
+const SOURCE_EXTENSIONS: &[&str] = &[
+    "c", "cc", "cp", "cpp", "cxx", "c++", "C", "CC", "CPP", "m", "mm", "M", "i", "ii", "mi", "mii",
+    "s", "S", "sx",
+];
+
+// 会产生额外输出文件或者结果依赖于外部状态的选项, 出现时不使用缓存
+const UNSUPPORTED_PREFIXES: &[&str] = &[
+    "-fprofile-",
+    "-fauto-profile",
+    "-fbranch-probabilities",
+    "-fcoverage-",
+    "-ftest-coverage",
+    "--coverage",
+    "-save-temps",
+    "-fdump-",
+    "-ftime-trace",
+    "-ftime-report",
+    "-fstack-usage",
+    "-fcallgraph-info",
+    "-gsplit-dwarf",
+    "-fmodules",
+    "-Wp,",
+    "-Xclang",
+    "-Xpreprocessor",
+];
+
+pub struct CompileJob {
+    key: String,
+    depfile: Option<String>,
+}
+
+// 缓存的编译结果, 输出文件在确定退出码之后由调用者写入
+pub struct CachedResult {
+    pub code: i32,
+    pub stdout: Vec<u8>,
+    pub stderr: Vec<u8>,
+    output: Vec<u8>,
+    depfile: Option<(String, Vec<u8>)>,
+}
+
+impl CachedResult {
+    // output 是真正的编译写入的路径, 使用 -clw-atomic-output 时为临时文件
+    pub fn restore(&self, work_dir: &str, output: &Path) -> Result<()> {
+        write_file(output, &self.output)?;
+        if let Some((ref depfile, ref data)) = self.depfile {
+            write_file(&resolve(work_dir, depfile), data)?;
+        }
+        Ok(())
+    }
+}
+
+// 先写入同一目录下的临时文件再重命名, 不会留下不完整的文件
+pub(crate) fn write_file(path: &Path, data: &[u8]) -> Result<()> {
+    let temp = path.with_file_name(format!(
+        ".{}.clw-{}",
+        path.file_name().unwrap_or_default().to_string_lossy(),
+        std::process::id()
+    ));
+    fs::write(&temp, data)?;
+    if let Err(e) = fs::rename(&temp, path) {
+        fs::remove_file(&temp).unwrap_or(());
+        return Err(e.into());
+    }
+    Ok(())
+}
+
+pub enum Lookup {
+    Hit(CachedResult),
+    Miss(CompileJob),
+    Bypass(String),
+}
+
+pub struct Cache {
+    dir: PathBuf,
+    max_size: u64,
+}
+
+#[derive(Default)]
+struct Stats {
+    hits: u64,
+    misses: u64,
+    bypasses: u64,
+    evictions: u64,
+    size: u64,
+}
+
+impl Stats {
+    fn parse(content: &str) -> Stats {
+        let mut stats = Stats::default();
+        for line in content.lines() {
+            let mut parts = line.split_whitespace();
+            let name = parts.next().unwrap_or("");
+            let value = parts.next().and_then(|v| v.parse().ok()).unwrap_or(0);
+            match name {
+                "hits" => stats.hits = value,
+                "misses" => stats.misses = value,
+                "bypasses" => stats.bypasses = value,
+                "evictions" => stats.evictions = value,
+                "size" => stats.size = value,
+                _ => {}
+            }
+        }
+        stats
+    }
+
+    fn format(&self) -> String {
+        format!(
+            "hits {}\nmisses {}\nbypasses {}\nevictions {}\nsize {}\n",
+            self.hits, self.misses, self.bypasses, self.evictions, self.size
+        )
+    }
+}
+
+impl Cache {
+    pub fn new(dir: &str, max_size: u64) -> Cache {
+        Cache {
+            dir: PathBuf::from(dir),
+            max_size,
+        }
+    }
+
+    pub fn lookup(&self, command: &str, work_dir: &str, arguments: &[String]) -> Result<Lookup> {
+        // 输出文件由调用者按照 -o 确定, 这里只检查是否存在
+        let (_, depfile) = match analyze(command, arguments) {
+            Ok(value) => value,
+            Err(reason) => {
+                self.update_stats(|s| s.bypasses += 1);
+                return Ok(Lookup::Bypass(reason));
+            }
+        };
+        let key = match compute_key(command, work_dir, arguments) {
+            Ok(key) => key,
+            Err(reason) => {
+                self.update_stats(|s| s.bypasses += 1);
+                return Ok(Lookup::Bypass(reason));
+            }
+        };
+        let job = CompileJob { key, depfile };
+        if let Ok(result) = self.load(&job) {
+            self.update_stats(|s| s.hits += 1);
+            Ok(Lookup::Hit(result))
+        } else {
+            self.update_stats(|s| s.misses += 1);
+            Ok(Lookup::Miss(job))
+        }
+    }
+
+    fn entry_path(&self, key: &str) -> PathBuf {
+        self.dir.join(&key[..2]).join(key)
+    }
+
+    // 一次读取整个缓存项, 之后被其它进程淘汰也不影响结果
+    fn load(&self, job: &CompileJob) -> Result<CachedResult> {
+        let entry = self.entry_path(&job.key);
+        let object = entry.join("output");
+        if !object.is_file() {
+            bail!("no cache entry");
+        }
+        let depfile = match job.depfile {
+            Some(ref depfile) => Some((depfile.clone(), fs::read(entry.join("depfile"))?)),
+            None => None,
+        };
+        let result = CachedResult {
+            code: fs::read_to_string(entry.join("status"))?.trim().parse()?,
+            stdout: fs::read(entry.join("stdout")).unwrap_or_default(),
+            stderr: fs::read(entry.join("stderr")).unwrap_or_default(),
+            output: fs::read(&object)?,
+            depfile,
+        };
+        // 命中时更新修改时间, 淘汰时按照最近使用顺序处理
+        File::options()
+            .write(true)
+            .open(&object)?
+            .set_modified(SystemTime::now())?;
+        Ok(result)
+    }
+
+    // 保存命令原始的退出码和输出, 命中时按照当前的规则重新处理
+    pub fn store(
+        &self,
+        job: &CompileJob,
+        output: &Path,
+        work_dir: &str,
+        code: i32,
+        stdout: &[u8],
+        stderr: &[u8],
+    ) -> Result<()> {
+        let entry = self.entry_path(&job.key);
+        let temp = self
+            .dir
+            .join("tmp")
+            .join(format!("{}.{}", job.key, std::process::id()));
+        fs::create_dir_all(&temp)?;
+        let result = (|| -> Result<u64> {
+            let mut size = fs::copy(output, temp.join("output"))?;
+            if let Some(ref depfile) = job.depfile {
+                size += fs::copy(resolve(work_dir, depfile), temp.join("depfile"))?;
+            }
+            fs::write(temp.join("status"), code.to_string())?;
+            for (name, data) in [("stdout", stdout), ("stderr", stderr)] {
+                if !data.is_empty() {
+                    fs::write(temp.join(name), data)?;
+                    size += data.len() as u64;
+                }
+            }
+            fs::create_dir_all(entry.parent().unwrap())?;
+            fs::rename(&temp, &entry)?;
+            Ok(size)
+        })();
+        // 其它进程可能已经写入了相同的缓存
+        fs::remove_dir_all(&temp).unwrap_or(());
+
+        let size = result?;
+        let mut evict = false;
+        self.update_stats(|s| {
+            s.size += size;
+            evict = s.size > self.max_size;
+        });
+        if evict {
+            self.evict();
+        }
+        Ok(())
+    }
+
+    fn evict(&self) {
+        let mut entries = vec![];
+        let mut total = 0;
+        for bucket in fs::read_dir(&self.dir).into_iter().flatten().flatten() {
+            let name = bucket.file_name().to_string_lossy().into_owned();
+            if name.len() != 2 || !bucket.path().is_dir() {
+                continue;
+            }
+            for entry in fs::read_dir(bucket.path()).into_iter().flatten().flatten() {
+                let path = entry.path();
+                let mut size = 0;
+                for file in fs::read_dir(&path).into_iter().flatten().flatten() {
+                    size += file.metadata().map(|m| m.len()).unwrap_or(0);
+                }
+                let used = fs::metadata(path.join("output"))
+                    .and_then(|m| m.modified())
+                    .unwrap_or(SystemTime::UNIX_EPOCH);
+                total += size;
+                entries.push((used, size, path));
+            }
+        }
+        entries.sort();
+
+        // 淘汰到上限的 90%, 避免每次写入都触发淘汰
+        let limit = self.max_size / 10 * 9;
+        let mut evicted = 0;
+        for (_, size, path) in entries {
+            if total <= limit {
+                break;
+            }
+            if fs::remove_dir_all(&path).is_ok() {
+                total -= size;
+                evicted += 1;
+            }
+        }
+        self.update_stats(|s| {
+            s.size = total;
+            s.evictions += evicted;
+        });
+    }
+
+    fn update_stats(&self, f: impl FnOnce(&mut Stats)) {
+        if fs::create_dir_all(&self.dir).is_err() {
+            return;
+        }
+        let lock = self.dir.join("stats.lock");
+        let mut locked = false;
+        for _ in 0..100 {
+            if OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&lock)
+                .is_ok()
+            {
+                locked = true;
+                break;
+            }
+            // 持有锁的进程可能已经被杀死
+            let stale = fs::metadata(&lock)
+                .and_then(|m| m.modified())
+                .map(|t| t.elapsed().unwrap_or_default() > Duration::from_secs(10))
+                .unwrap_or(false);
+            if stale {
+                fs::remove_file(&lock).unwrap_or(());
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        if !locked {
+            return;
+        }
+        let path = self.dir.join("stats");
+        let mut stats = Stats::parse(&fs::read_to_string(&path).unwrap_or_default());
+        f(&mut stats);
+        fs::write(&path, stats.format()).unwrap_or(());
+        fs::remove_file(&lock).unwrap_or(());
+    }
+}
+
+fn resolve(work_dir: &str, path: &str) -> PathBuf {
+    Path::new(work_dir).join(path)
+}
+
+// 检查是否是可以缓存的单个源文件编译, 返回输出文件和依赖文件
+fn analyze(
+    command: &str,
+    arguments: &[String],
+) -> std::result::Result<(String, Option<String>), String> {
+    if ToolKind::detect(command) != ToolKind::Compiler {
+        return Err(format!("{} is not a supported compiler", command));
+    }
+    let mut compile = false;
+    let mut output = None;
+    let mut depfile = None;
+    let mut generate_depfile = false;
+    let mut sources = 0;
+
+    for parsed in argument::parse(ToolKind::Compiler, arguments) {
+        let arg = arguments[parsed.index].as_str();
+        if let Some(prefix) = UNSUPPORTED_PREFIXES.iter().find(|p| arg.starts_with(*p)) {
+            return Err(format!("unsupported option {}", prefix));
+        }
+        if parsed.is("-o") {
+            output = parsed.value;
+            continue;
+        } else if parsed.is("-MF") {
+            depfile = parsed.value;
+            continue;
+        } else if parsed.option.is_some() {
+            continue;
+        }
+        match arg {
+            "-c" => compile = true,
+            "-E" | "-S" | "-M" | "-MM" | "-fsyntax-only" => {
+                return Err(format!("{} does not produce an object file", arg))
+            }
+            "-" => return Err("reading from stdin".to_string()),
+            "-MD" | "-MMD" => generate_depfile = true,
+            _ if arg.starts_with('@') => return Err("nested response file".to_string()),
+            _ if arg.starts_with('-') => {}
+            _ => {
+                let extension = Path::new(arg)
+                    .extension()
+                    .map(|e| e.to_string_lossy().into_owned())
+                    .unwrap_or_default();
+                if !SOURCE_EXTENSIONS.contains(&extension.as_str()) {
+                    return Err(format!("unsupported input {}", arg));
+                }
+                sources += 1;
+            }
+        }
+    }
+
+    if !compile {
+        return Err("not a compilation".to_string());
+    }
+    if sources != 1 {
+        return Err(format!("{} source files", sources));
+    }
+    let output = output.ok_or("no output file".to_string())?;
+    let depfile = if generate_depfile {
+        // 没有 -MF 时 gcc/clang 使用输出文件替换后缀为 .d
+        Some(depfile.unwrap_or_else(|| {
+            Path::new(&output)
+                .with_extension("d")
+                .to_string_lossy()
+                .into_owned()
+        }))
+    } else {
+        None
+    };
+    Ok((output, depfile))
+}
+
+fn preprocess_arguments(arguments: &[String]) -> Vec<String> {
+    let mut result = vec![];
+    for parsed in argument::parse(ToolKind::Compiler, arguments) {
+        let tokens = parsed.tokens(arguments);
+        let skip = match parsed.option {
+            Some(option) => ["-o", "-MF", "-MT", "-MQ"].contains(&option),
+            None => ["-c", "-MD", "-MMD", "-MP"].contains(&tokens[0].as_str()),
+        };
+        if !skip {
+            result.extend(tokens.iter().cloned());
+        }
+    }
+    result.push("-E".to_string());
+    result
+}
+
+fn compute_key(
+    command: &str,
+    work_dir: &str,
+    arguments: &[String],
+) -> std::result::Result<String, String> {
+    let identity = program_identity(command).ok_or(format!("{} not found", command))?;
+
+    let mut preprocess = Command::new(command);
+    preprocess
+        .args(preprocess_arguments(arguments))
+        .stdin(Stdio::null())
+        .stderr(Stdio::null());
+    if !work_dir.is_empty() {
+        preprocess.current_dir(work_dir);
+    }
+    let output = preprocess
+        .output()
+        .map_err(|e| format!("preprocessor failed: {}", e))?;
+    if !output.status.success() {
+        return Err("preprocessor failed".to_string());
+    }
+
+    let mut hasher = Sha256::new();
+    let mut update = |data: &[u8]| {
+        hasher.update((data.len() as u64).to_le_bytes());
+        hasher.update(data);
+    };
+    update(CACHE_VERSION.as_bytes());
+    update(identity.as_bytes());
+    // 调试信息中包含编译目录
+    if arguments.iter().any(|arg| arg.starts_with("-g")) {
+        let dir = fs::canonicalize(if work_dir.is_empty() { "." } else { work_dir })
+            .map_err(|e| e.to_string())?;
+        update(dir.to_string_lossy().as_bytes());
+    }
+    for arg in arguments {
+        update(arg.as_bytes());
+    }
+    update(&output.stdout);
+
+    Ok(hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect())
+}
+
+// 支持 K/M/G 后缀, 如 500M, 5G
+pub fn parse_size(value: &str) -> Option<u64> {
+    let value = value.trim();
+    let (number, unit) = match value.char_indices().find(|(_, c)| !c.is_ascii_digit()) {
+        Some((index, _)) => value.split_at(index),
+        None => (value, ""),
+    };
+    let multiplier = match unit.to_ascii_uppercase().as_str() {
+        "" | "B" => 1,
+        "K" | "KB" | "KIB" => 1024,
+        "M" | "MB" | "MIB" => 1024 * 1024,
+        "G" | "GB" | "GIB" => 1024 * 1024 * 1024,
+        _ => return None,
+    };
+    number.parse::<u64>().ok().map(|n| n * multiplier)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(args: &[&str]) -> Vec<String> {
+        args.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_analyze() {
+        let (output, depfile) = analyze(
+            "gcc",
+            &strings(&["-c", "-I", "inc", "src/a.c", "-o", "out/a.o", "-MD"]),
+        )
+        .unwrap();
+        assert_eq!(output, "out/a.o");
+        assert_eq!(depfile.as_deref(), Some("out/a.d"));
+
+        let (_, depfile) = analyze(
+            "clang",
+            &strings(&["-c", "a.c", "-oa.o", "-MMD", "-MF", "a.dep"]),
+        )
+        .unwrap();
+        assert_eq!(depfile.as_deref(), Some("a.dep"));
+
+        assert!(analyze("ld", &strings(&["-c", "a.c", "-o", "a.o"])).is_err());
+        assert!(analyze("gcc", &strings(&["a.c", "-o", "a"])).is_err());
+        assert!(analyze("gcc", &strings(&["-c", "a.c", "b.c", "-o", "a.o"])).is_err());
+        assert!(analyze("gcc", &strings(&["-c", "a.c", "-o", "a.o", "--coverage"])).is_err());
+    }
+
+    #[test]
+    fn test_preprocess_arguments() {
+        assert_eq!(
+            preprocess_arguments(&strings(&[
+                "-c", "a.c", "-o", "a.o", "-MD", "-MF", "a.d", "-MTa.o", "-O2"
+            ])),
+            strings(&["a.c", "-O2", "-E"])
+        );
+    }
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_size("100"), Some(100));
+        assert_eq!(parse_size("2K"), Some(2048));
+        assert_eq!(parse_size("5G"), Some(5 * 1024 * 1024 * 1024));
+        assert_eq!(parse_size("1x"), None);
+    }
+}
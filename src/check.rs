@@ -0,0 +1,244 @@
+use anyhow::Result;
+use std::fs;
+
+use cli_wrapper::rule::{self, Syntax, PREFIX};
+use cli_wrapper::{parse_arguments, CommandType, Configuration, Rule};
+
+const CHECK_CONFIG_USAGE: &str = "\
+Usage: cli-wrapper check-config <file>...
+
+Parse -clw-config.txt files with the same rules as the wrapper and report every line
+that would be ignored or forwarded to the command, with line and column numbers and
+spelling suggestions. Exits with 1 when a problem is found.";
+
+#[derive(Debug, PartialEq)]
+pub struct Diagnostic {
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+fn levenshtein(a: &[char], b: &[char]) -> usize {
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.iter().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let cost = if ca == cb { 0 } else { 1 };
+            current.push(
+                (previous[j] + cost)
+                    .min(previous[j + 1] + 1)
+                    .min(current[j] + 1),
+            );
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+// 按照编辑距离查找最接近的规则, -clw-replce--lm=-lm2 这种规则名称后面紧跟参数, 只比较相同长度的前缀
+fn suggest(key: &str) -> Option<&'static dyn Rule> {
+    let key: Vec<char> = key.chars().collect();
+    let name_end = key.iter().position(|c| *c == '=').unwrap_or(key.len());
+    rule::builtin()
+        .rules()
+        .filter_map(|rule| {
+            let distance = match rule.syntax() {
+                Syntax::Pair(_, _) => {
+                    let name: Vec<char> = format!("{}-", rule.name()).chars().collect();
+                    (name.len().saturating_sub(2)..=name.len() + 2)
+                        .filter(|len| *len <= name_end)
+                        .map(|len| levenshtein(&name, &key[..len]))
+                        .min()?
+                }
+                _ => {
+                    let name: Vec<char> = rule.name().chars().collect();
+                    levenshtein(&name, &key[..name_end])
+                }
+            };
+            (distance <= (rule.name().len() / 3).max(1)).then_some((distance, rule))
+        })
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, rule)| rule)
+}
+
+fn check_rule(key: &str, line: usize, diagnostics: &mut Vec<Diagnostic>) {
+    // 列号从 1 开始, 规则名称位于 -clw- 之后
+    let column = |offset: usize| PREFIX.len() + key[..offset].chars().count() + 1;
+    let mut report = |offset: usize, message: String| {
+        diagnostics.push(Diagnostic {
+            line,
+            column: column(offset),
+            message,
+        })
+    };
+
+    let parsed = parse_arguments(&mut Configuration::new(), key);
+    let ignored = matches!(parsed, CommandType::Ignore);
+    let matched = rule::builtin().find(key);
+    let (rule, rest) = match matched {
+        Some(matched) => matched,
+        None => {
+            let name = key.split('=').next().unwrap_or(key);
+            let mut message = format!(
+                "unknown rule {}{} is forwarded to the command",
+                PREFIX, name
+            );
+            if let Some(rule) = suggest(key) {
+                message += &format!(", did you mean {}?", rule.usage());
+            }
+            report(0, message);
+            return;
+        }
+    };
+    let offset = key.len() - rest.len();
+    match rule.syntax() {
+        Syntax::Pair(before, after) => match rest.split_once('=') {
+            None => report(
+                key.len(),
+                format!("missing '=<{}>' in {}", after, rule.usage()),
+            ),
+            Some(("", _)) => report(
+                offset,
+                format!("empty <{}> value in {}", before, rule.usage()),
+            ),
+            Some((value, "")) => report(
+                offset + value.len() + 1,
+                format!("empty <{}> value in {}", after, rule.usage()),
+            ),
+            _ => {}
+        },
+        Syntax::Value(value) if rest.is_empty() => report(
+            offset,
+            format!("empty <{}> value in {}", value, rule.usage()),
+        ),
+        _ if ignored => report(offset, format!("invalid value for {}", rule.usage())),
+        _ => {
+            if let CommandType::Invalid(message) = parsed {
+                report(offset, message);
+            }
+        }
+    }
+}
+
+pub fn check_content(content: &str) -> Vec<Diagnostic> {
+    let mut diagnostics = vec![];
+    for (index, line) in content.replace("\r\n", "\n").lines().enumerate() {
+        let number = index + 1;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let key = match line.strip_prefix(PREFIX) {
+            Some(key) => key,
+            None => {
+                diagnostics.push(Diagnostic {
+                    line: number,
+                    column: 1,
+                    message: format!("line does not start with {} and is ignored", PREFIX),
+                });
+                continue;
+            }
+        };
+        let trimmed = line.trim_end();
+        if trimmed.len() != line.len() {
+            diagnostics.push(Diagnostic {
+                line: number,
+                column: trimmed.chars().count() + 1,
+                message: "trailing whitespace is kept as part of the value".to_string(),
+            });
+        }
+        check_rule(key, number, &mut diagnostics);
+    }
+    diagnostics
+}
+
+pub fn check_config_command(args: impl Iterator<Item = String>) -> Result<i32> {
+    let files: Vec<String> = args.collect();
+    if files.iter().any(|f| f == "-h" || f == "--help") {
+        println!("{}", CHECK_CONFIG_USAGE);
+        return Ok(0);
+    }
+    if files.is_empty() {
+        eprintln!("{}", CHECK_CONFIG_USAGE);
+        return Ok(1);
+    }
+
+    let mut problems = 0;
+    for file in files.iter() {
+        let content = fs::read_to_string(file)?;
+        let lines: Vec<&str> = content.lines().collect();
+        for diagnostic in check_content(&content) {
+            println!(
+                "{}:{}:{}: {}",
+                file, diagnostic.line, diagnostic.column, diagnostic.message
+            );
+            let line = lines[diagnostic.line - 1].trim_end_matches('\r');
+            println!("  {}", line);
+            println!("  {}^", " ".repeat(diagnostic.column - 1));
+            problems += 1;
+        }
+    }
+    if problems > 0 {
+        println!("{} problem(s) found", problems);
+        return Ok(1);
+    }
+    Ok(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rules_are_known() {
+        for rule in rule::builtin().rules() {
+            let keys = match rule.syntax() {
+                Syntax::Flag => vec![rule.name().to_string()],
+                Syntax::Value(_) | Syntax::OptionalValue(_) => {
+                    // -clw-output-checksum 只接受算法名称
+                    vec![
+                        format!("{}=1", rule.name()),
+                        format!("{}=1=2", rule.name()),
+                        format!("{}=sha256", rule.name()),
+                    ]
+                }
+                Syntax::Pair(_, _) => vec![format!("{}-a=b", rule.name())],
+            };
+            let known = keys.iter().any(|key| {
+                !matches!(
+                    parse_arguments(&mut Configuration::new(), key),
+                    CommandType::Ignore
+                )
+            });
+            assert!(known, "{}", rule.name());
+        }
+    }
+
+    #[test]
+    fn test_check_content() {
+        let content = "\
+-clw-remove=-lm
+-clw-replce--lm=-lm2
+-clw-move-back-before-=-lc
+-clw-replace--lm=
+-clw-remove=
+clw-just-print
+-clw-cache-max-size=abc
+-clw-static-link=-lc\x20
+";
+        let diagnostics = check_content(content);
+        let positions: Vec<(usize, usize)> =
+            diagnostics.iter().map(|d| (d.line, d.column)).collect();
+        assert_eq!(
+            positions,
+            vec![(2, 6), (3, 23), (4, 18), (5, 13), (6, 1), (7, 21), (8, 21)]
+        );
+        assert!(diagnostics[0]
+            .message
+            .contains("did you mean -clw-replace-<before>=<after>?"));
+        assert!(diagnostics[1].message.contains("empty <before> value"));
+        assert!(diagnostics[2].message.contains("empty <after> value"));
+        assert!(diagnostics[3].message.contains("empty <arg> value"));
+        assert!(diagnostics[5].message.contains("invalid value"));
+        assert!(diagnostics[6].message.contains("trailing whitespace"));
+    }
+}
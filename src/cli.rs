@@ -0,0 +1,106 @@
+pub const USAGE: &str = "\
+Usage: cli-wrapper <subcommand> [options]
+       cli-wrapper <command> [arguments] [-clw-<rule>...]
+
+Subcommands:
+  run           Run a command with the -clw- rules applied
+  print         Print the final command without running it
+  explain       Show how every -clw- rule changes the arguments
+  check-config  Validate a -clw-config.txt file
+  install       Create symlinks that wrap the tools of a toolchain
+  report        Aggregate the records written by -clw-json-log
+  version       Print the version
+  help          Print this help
+
+An unknown subcommand is treated as the command to run, i.e. `cli-wrapper gcc ...` is the same
+as `cli-wrapper run gcc ...`. When invoked under any other name (a renamed copy or a symlink
+created by install) cli-wrapper wraps that tool directly. Run `cli-wrapper <subcommand> --help`
+for the options of a subcommand.";
+
+pub const RUN_USAGE: &str = "\
+Usage: cli-wrapper run <command> [arguments] [-clw-<rule>...]
+
+Apply the -clw- rules from the arguments and from <exe>-clw-config.txt, then run the command.
+The exit code of the command is returned.";
+
+pub const PRINT_USAGE: &str = "\
+Usage: cli-wrapper print <command> [arguments] [-clw-<rule>...]
+
+Apply the -clw- rules and print the final command without running it, the same as -clw-just-print.";
+
+pub const EXPLAIN_USAGE: &str = "\
+Usage: cli-wrapper explain <command> [arguments] [-clw-<rule>...]
+
+Apply the -clw- rules one by one and print the arguments added and removed by each of them,
+then print the final command without running it. -clw-explain does the same in wrapped mode.";
+
+pub const VERSION_USAGE: &str = "\
+Usage: cli-wrapper version
+
+Print the version of cli-wrapper.";
+
+pub fn version() -> String {
+    format!("cli-wrapper {}", env!("CARGO_PKG_VERSION"))
+}
+
+// 基于最长公共子序列的参数差异, - 表示删除, + 表示添加
+fn diff(before: &[String], after: &[String]) -> Vec<String> {
+    let (n, m) = (before.len(), after.len());
+    let mut lcs = vec![vec![0usize; m + 1]; n + 1];
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            lcs[i][j] = if before[i] == after[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+    let mut lines = vec![];
+    let (mut i, mut j) = (0, 0);
+    while i < n || j < m {
+        if i < n && j < m && before[i] == after[j] {
+            i += 1;
+            j += 1;
+        } else if i < n && (j == m || lcs[i + 1][j] >= lcs[i][j + 1]) {
+            lines.push(format!("- {}", before[i]));
+            i += 1;
+        } else {
+            lines.push(format!("+ {}", after[j]));
+            j += 1;
+        }
+    }
+    lines
+}
+
+pub fn explain_rule(rule: &str, before: &[String], after: &[String]) {
+    println!("rule: {}", rule);
+    let lines = diff(before, after);
+    if lines.is_empty() {
+        println!("  (no change)");
+    }
+    for line in lines {
+        println!("  {}", line);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(args: &[&str]) -> Vec<String> {
+        args.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_diff() {
+        let before = strings(&["a.o", "-lm", "-lc", "-o", "a"]);
+        let after = strings(&["a.o", "-lc", "-lm2", "-o", "a"]);
+        assert_eq!(diff(&before, &after), vec!["- -lm", "+ -lm2"]);
+        assert!(diff(&before, &before).is_empty());
+        assert_eq!(
+            diff(&strings(&["-lm"]), &strings(&["-lm2"])),
+            vec!["- -lm", "+ -lm2"]
+        );
+    }
+}
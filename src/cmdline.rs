@@ -0,0 +1,189 @@
+use anyhow::{bail, Result};
+use simplelog::{debug, warn};
+use std::collections::hash_map::RandomState;
+use std::env;
+use std::fs::{self, OpenOptions};
+use std::hash::BuildHasher;
+use std::io::{ErrorKind, Write};
+use std::path::PathBuf;
+use std::time::SystemTime;
+
+use crate::argument::ToolKind;
+use crate::report::tool_name;
+use crate::{Configuration, ResponseFile};
+
+// Linux 中单个参数的长度限制 MAX_ARG_STRLEN
+#[cfg(target_os = "linux")]
+const MAX_ARGUMENT_LENGTH: usize = 32 * 4096;
+
+// 不支持 @file 的工具和它们自己的写法
+const UNSUPPORTED_TOOLS: &[(&str, &str)] = &[
+    ("nvcc", "--options-file <file>"),
+    ("armcc", "--via <file>"),
+    ("armasm", "--via <file>"),
+    ("armlink", "--via <file>"),
+    ("ld64", "-filelist <file> for the input files"),
+];
+
+// CreateProcess 的命令行最多 32767 个字符
+#[cfg(windows)]
+pub fn platform_limit() -> usize {
+    32767
+}
+
+// ARG_MAX 包括环境变量
+#[cfg(unix)]
+pub fn platform_limit() -> usize {
+    let arg_max = unsafe { libc::sysconf(libc::_SC_ARG_MAX) };
+    let arg_max = if arg_max > 0 {
+        arg_max as usize
+    } else {
+        128 * 1024
+    };
+    let environment: usize = env::vars_os()
+        .map(|(k, v)| k.len() + v.len() + 2 + size_of::<usize>())
+        .sum();
+    // 保留一部分空间给 execve 和被包装的工具添加的环境变量
+    arg_max.saturating_sub(environment).saturating_sub(4096)
+}
+
+#[cfg(not(any(unix, windows)))]
+pub fn platform_limit() -> usize {
+    32767
+}
+
+// 按照平台传递参数的方式计算命令行长度
+fn command_line_length(command: &str, arguments: &[String]) -> usize {
+    let args = [command.to_string()]
+        .into_iter()
+        .chain(arguments.iter().cloned());
+    if cfg!(windows) {
+        args.map(|arg| ResponseFile::escape(&arg).len() + 1).sum()
+    } else {
+        args.map(|arg| arg.len() + 1 + size_of::<usize>()).sum()
+    }
+}
+
+fn exceeds_limit(config: &Configuration, limit: usize) -> bool {
+    #[cfg(target_os = "linux")]
+    if config
+        .arguments
+        .iter()
+        .any(|arg| arg.len() >= MAX_ARGUMENT_LENGTH)
+    {
+        return true;
+    }
+    command_line_length(&config.command, &config.arguments) > limit
+}
+
+fn check_support(command: &str) -> Result<()> {
+    let name = tool_name(command).to_lowercase();
+    if let Some((tool, alternative)) = UNSUPPORTED_TOOLS.iter().find(|(tool, _)| name == *tool) {
+        bail!(
+            "the command line of {} is too long and {} does not support @file response files, pass the arguments with {} instead",
+            command,
+            tool,
+            alternative
+        );
+    }
+    if ToolKind::detect(command) == ToolKind::Unknown && name != "ar" && !name.ends_with("-ar") {
+        warn!(
+            "{} is not a known tool, assuming it supports @file response files",
+            command
+        );
+    }
+    Ok(())
+}
+
+// 临时的响应文件, 命令结束后删除
+pub struct CommandFile {
+    pub path: PathBuf,
+}
+
+impl Drop for CommandFile {
+    fn drop(&mut self) {
+        fs::remove_file(&self.path).unwrap_or(());
+    }
+}
+
+// 临时目录是共享的, 使用随机的文件名并且只创建新文件, 避免覆盖其它用户预先创建的文件或者符号链接
+fn create_command_file(content: &str) -> Result<CommandFile> {
+    let dir = env::temp_dir();
+    for _ in 0..100 {
+        let suffix = RandomState::new().hash_one(SystemTime::now());
+        let path = dir.join(format!(
+            "clw_cmd_{}_{:016x}.rsp",
+            std::process::id(),
+            suffix
+        ));
+        match OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(mut file) => {
+                let command_file = CommandFile { path };
+                file.write_all(content.as_bytes())?;
+                return Ok(command_file);
+            }
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e.into()),
+        }
+    }
+    bail!(
+        "failed to create a response file in {}",
+        dir.to_string_lossy()
+    )
+}
+
+// 命令行超过限制时将全部参数写入响应文件, 返回 None 表示直接传递参数
+pub fn prepare(config: &Configuration) -> Result<Option<CommandFile>> {
+    let limit = if config.max_command_line > 0 {
+        config.max_command_line as usize
+    } else {
+        platform_limit()
+    };
+    if !exceeds_limit(config, limit) {
+        return Ok(None);
+    }
+    check_support(&config.command)?;
+    let content: Vec<String> = config
+        .arguments
+        .iter()
+        .map(|arg| ResponseFile::escape(arg))
+        .collect();
+    let file = create_command_file(&content.join(" "))?;
+    debug!(
+        "the command line exceeds {} bytes, passing the arguments with @{}",
+        limit,
+        file.path.to_string_lossy()
+    );
+    Ok(Some(file))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_prepare() {
+        let mut config = Configuration::new();
+        config.command = "gcc".to_owned();
+        config.arguments = ["-c", "my file.c", "-DX=\"1\"", "-o", "a.o"]
+            .map(String::from)
+            .to_vec();
+        config.max_command_line = 1000;
+        assert!(prepare(&config).unwrap().is_none());
+
+        config.max_command_line = 10;
+        let file = prepare(&config).unwrap().unwrap();
+        let content = fs::read_to_string(&file.path).unwrap();
+        assert_eq!(content, "-c \"my file.c\" \"-DX=\\\"1\\\"\" -o a.o");
+        assert_eq!(ResponseFile::parse(&content), config.arguments);
+        let other = prepare(&config).unwrap().unwrap();
+        assert_ne!(file.path, other.path);
+        let path = file.path.clone();
+        drop(file);
+        assert!(!path.exists());
+
+        config.command = "/usr/local/cuda/bin/nvcc".to_owned();
+        let error = prepare(&config).err().unwrap().to_string();
+        assert!(error.contains("--options-file"), "{}", error);
+    }
+}
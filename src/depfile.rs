@@ -0,0 +1,272 @@
+use anyhow::Result;
+use simplelog::debug;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+use crate::argument;
+use crate::Configuration;
+
+// 依赖文件中的一条规则, 如 a.o: a.c a.h, -MP 生成的规则没有依赖
+#[derive(Debug, PartialEq)]
+struct DepRule {
+    targets: Vec<String>,
+    prerequisites: Vec<String>,
+}
+
+enum Token {
+    Word(String),
+    // 单独的 : 或者位于文件名末尾的 :
+    Colon,
+    Newline,
+}
+
+// C:\a.h 中的 : 后面不是空白, 不会出现在末尾
+fn finish_word(word: &mut String, tokens: &mut Vec<Token>) {
+    if word.is_empty() {
+        return;
+    }
+    match word.strip_suffix(':') {
+        Some(name) => {
+            if !name.is_empty() {
+                tokens.push(Token::Word(name.to_string()));
+            }
+            tokens.push(Token::Colon);
+        }
+        None => tokens.push(Token::Word(word.clone())),
+    }
+    word.clear();
+}
+
+// 按照 gcc 写入依赖文件的转义规则解析, 空格和 # 前面的 2n+1 个反斜杠表示 n 个反斜杠和转义的字符, $$ 表示 $
+fn tokenize(content: &str) -> Vec<Token> {
+    let content = content.replace("\\\r\n", " ").replace("\\\n", " ");
+    let mut tokens = vec![];
+    let mut word = String::new();
+    let mut chars = content.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                let mut count = 1;
+                while chars.peek() == Some(&'\\') {
+                    chars.next();
+                    count += 1;
+                }
+                match chars.peek() {
+                    Some(' ' | '\t' | '#') => {
+                        word.push_str(&"\\".repeat(count / 2));
+                        if count % 2 == 1 {
+                            word.push(chars.next().unwrap());
+                        }
+                    }
+                    _ => word.push_str(&"\\".repeat(count)),
+                }
+            }
+            '$' if chars.peek() == Some(&'$') => {
+                chars.next();
+                word.push('$');
+            }
+            ' ' | '\t' | '\r' => finish_word(&mut word, &mut tokens),
+            '\n' => {
+                finish_word(&mut word, &mut tokens);
+                tokens.push(Token::Newline);
+            }
+            _ => word.push(c),
+        }
+    }
+    finish_word(&mut word, &mut tokens);
+    tokens
+}
+
+fn parse(content: &str) -> Vec<DepRule> {
+    let mut rules = vec![];
+    let mut words = vec![];
+    let mut targets = None;
+    let mut finish = |targets: &mut Option<Vec<String>>, words: &mut Vec<String>| {
+        if let Some(targets) = targets.take() {
+            rules.push(DepRule {
+                targets,
+                prerequisites: std::mem::take(words),
+            });
+        }
+        words.clear();
+    };
+    for token in tokenize(content) {
+        match token {
+            Token::Word(word) => words.push(word),
+            Token::Colon if targets.is_none() => targets = Some(std::mem::take(&mut words)),
+            Token::Colon => words.push(":".to_string()),
+            Token::Newline => finish(&mut targets, &mut words),
+        }
+    }
+    finish(&mut targets, &mut words);
+    rules
+}
+
+fn escape(path: &str) -> String {
+    let mut result = String::new();
+    let mut backslashes = 0;
+    for c in path.chars() {
+        match c {
+            ' ' | '\t' | '#' => {
+                result.push_str(&"\\".repeat(backslashes + 1));
+                result.push(c);
+            }
+            '$' => result.push_str("$$"),
+            _ => result.push(c),
+        }
+        backslashes = if c == '\\' { backslashes + 1 } else { 0 };
+    }
+    result
+}
+
+fn format(rules: &[DepRule]) -> String {
+    let mut result = String::new();
+    for (i, rule) in rules.iter().enumerate() {
+        if i > 0 {
+            result.push('\n');
+        }
+        let targets: Vec<String> = rule.targets.iter().map(|t| escape(t)).collect();
+        result.push_str(&targets.join(" "));
+        result.push(':');
+        for prerequisite in rule.prerequisites.iter() {
+            result.push_str(" \\\n ");
+            result.push_str(&escape(prerequisite));
+        }
+        result.push('\n');
+    }
+    result
+}
+
+// 相对于 base 的路径, 只有根目录相同时保持绝对路径, 如 /usr/include 下的系统头文件
+fn relative_to(path: &Path, base: &Path) -> Option<PathBuf> {
+    let path: Vec<Component> = path.components().collect();
+    let base: Vec<Component> = base.components().collect();
+    let common = path
+        .iter()
+        .zip(base.iter())
+        .take_while(|(a, b)| a == b)
+        .count();
+    if path[..common]
+        .iter()
+        .all(|c| matches!(c, Component::RootDir | Component::Prefix(_)))
+    {
+        return None;
+    }
+    let mut result = PathBuf::new();
+    for _ in common..base.len() {
+        result.push("..");
+    }
+    for component in &path[common..] {
+        result.push(component);
+    }
+    if result.as_os_str().is_empty() {
+        result.push(".");
+    }
+    Some(result)
+}
+
+fn rewrite_path(config: &Configuration, path: &str, base: &Path) -> String {
+    let mut path = path.to_string();
+    if let Some((from, to)) = config
+        .depfile_rewrites
+        .iter()
+        .find(|(from, _)| path.starts_with(from.as_str()))
+    {
+        path = format!("{}{}", to, &path[from.len()..]);
+    }
+    if config.depfile_relative && Path::new(&path).is_absolute() {
+        if let Some(relative) = relative_to(Path::new(&path), base) {
+            path = relative.to_string_lossy().into_owned();
+        }
+    }
+    path
+}
+
+// 命令成功后改写依赖文件中的路径
+pub fn rewrite_depfile(config: &Configuration) -> Result<()> {
+    if config.depfile_rewrites.is_empty() && !config.depfile_relative {
+        return Ok(());
+    }
+    let depfile = match argument::find_depfile(&config.expanded_arguments()) {
+        Some(depfile) => config.resolve_path(&depfile)?,
+        None => {
+            debug!("the command does not generate a depfile");
+            return Ok(());
+        }
+    };
+    let base = fs::canonicalize(config.resolve_path(".")?)?;
+    let mut rules = parse(&fs::read_to_string(&depfile)?);
+    for rule in rules.iter_mut() {
+        for path in rule.targets.iter_mut().chain(rule.prerequisites.iter_mut()) {
+            *path = rewrite_path(config, path, &base);
+        }
+    }
+    fs::write(&depfile, format(&rules))?;
+    debug!("rewrote depfile {}", depfile.to_string_lossy());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(values: &[&str]) -> Vec<String> {
+        values.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_parse() {
+        let content = "obj/a.o: src/a.c /tmp/sb-1/my\\ dir/a.h \\\n  C:\\sdk\\b.h x\\#y.h \\\\\\ z.h $$c.h\n\n/tmp/sb-1/my\\ dir/a.h:\n";
+        let rules = parse(content);
+        assert_eq!(
+            rules,
+            vec![
+                DepRule {
+                    targets: strings(&["obj/a.o"]),
+                    prerequisites: strings(&[
+                        "src/a.c",
+                        "/tmp/sb-1/my dir/a.h",
+                        "C:\\sdk\\b.h",
+                        "x#y.h",
+                        "\\ z.h",
+                        "$c.h"
+                    ]),
+                },
+                DepRule {
+                    targets: strings(&["/tmp/sb-1/my dir/a.h"]),
+                    prerequisites: vec![],
+                },
+            ]
+        );
+        assert_eq!(parse(&format(&rules)), rules);
+    }
+
+    #[test]
+    fn test_rewrite_depfile() {
+        let dir = std::env::temp_dir().join(format!("clw_depfile_{}", std::process::id()));
+        fs::create_dir_all(dir.join("build")).unwrap();
+        let dir = fs::canonicalize(dir).unwrap();
+        let depfile = format!(
+            "a.o: {0}/src/a.c /tmp/sb-1/inc/a\\ b.h /usr/include/stdio.h\n",
+            dir.to_string_lossy()
+        );
+        fs::write(dir.join("build/a.d"), depfile).unwrap();
+
+        let mut config = Configuration::new();
+        config.command = "gcc".to_owned();
+        config.work_dir = dir.join("build").to_string_lossy().into_owned();
+        config.arguments = strings(&["-c", "../src/a.c", "-MMD", "-o", "a.o"]);
+        config.depfile_rewrites = vec![(
+            "/tmp/sb-1/".to_owned(),
+            format!("{}/", dir.to_string_lossy()),
+        )];
+        config.depfile_relative = true;
+        rewrite_depfile(&config).unwrap();
+        assert_eq!(
+            fs::read_to_string(dir.join("build/a.d")).unwrap(),
+            "a.o: \\\n ../src/a.c \\\n ../inc/a\\ b.h \\\n /usr/include/stdio.h\n"
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,235 @@
+use regex::Regex;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::sync::OnceLock;
+
+// 诊断行, 如 a.c:1:2: warning: ... 或者 cc1: error: ..., 除了 note 以外都开始一个新的诊断
+fn primary_pattern() -> &'static Regex {
+    static PATTERN: OnceLock<Regex> = OnceLock::new();
+    PATTERN.get_or_init(|| {
+        Regex::new(r"^(\S.*?:\d+(:\d+)?|[^\s:]+): (warning|error|fatal error|remark|note): ")
+            .unwrap()
+    })
+}
+
+// 诊断之前的上下文, 属于随后的诊断
+fn context_pattern() -> &'static Regex {
+    static PATTERN: OnceLock<Regex> = OnceLock::new();
+    PATTERN.get_or_init(|| {
+        Regex::new(
+            r"^(In file included from |\s+from |In (instantiation|substitution) of |\S.*?: (In .*|At (top level|global scope)):$|\d+ (warning|error)s? generated\.$)",
+        )
+        .unwrap()
+    })
+}
+
+fn ansi_pattern() -> &'static Regex {
+    static PATTERN: OnceLock<Regex> = OnceLock::new();
+    PATTERN.get_or_init(|| Regex::new(r"\x1b\[[0-9;]*[mK]").unwrap())
+}
+
+// 按照诊断块过滤和改写 stderr, 一个 GCC/Clang 诊断和它的上下文, note, 源码和 ^ 行作为一个整体
+#[derive(Clone, Default)]
+pub struct DiagnosticFilter {
+    filters: Vec<Regex>,
+    rewrites: Vec<(Regex, String)>,
+    block: Vec<String>,
+    // 主诊断行和它之前的包含上下文, 过滤规则只匹配这些行
+    header: Vec<String>,
+    has_primary: bool,
+    // error 和 fatal error 不会被过滤
+    is_error: bool,
+    // 主诊断之后的上下文, 属于随后的 note 或者下一个诊断
+    pending: Vec<(String, String)>,
+}
+
+impl DiagnosticFilter {
+    pub fn new(filters: &[Regex], rewrites: &[(Regex, String)]) -> DiagnosticFilter {
+        DiagnosticFilter {
+            filters: filters.to_vec(),
+            rewrites: rewrites.to_vec(),
+            ..Default::default()
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.filters.is_empty() && self.rewrites.is_empty()
+    }
+
+    // 输入一行(包括换行符), 返回可以输出的内容
+    pub fn push(&mut self, line: &str) -> String {
+        let mut line = line.to_string();
+        for (pattern, replacement) in self.rewrites.iter() {
+            line = pattern
+                .replace_all(&line, replacement.as_str())
+                .into_owned();
+        }
+        let plain = ansi_pattern()
+            .replace_all(line.trim_end_matches(['\r', '\n']), "")
+            .into_owned();
+        let kind = primary_pattern()
+            .captures(&plain)
+            .map(|c| c.get(3).unwrap().as_str().to_string());
+        let is_note = kind.as_deref() == Some("note");
+
+        let mut output = String::new();
+        if kind.is_some() && !(is_note && self.has_primary) {
+            // 之前的上下文属于新的诊断
+            if self.has_primary {
+                output = self.finish_block();
+            }
+            for (line, plain) in self.pending.drain(..) {
+                self.block.push(line);
+                self.header.push(plain);
+            }
+            self.has_primary = true;
+            self.is_error = matches!(kind.as_deref(), Some("error" | "fatal error"));
+            self.header.push(plain);
+        } else if self.has_primary && context_pattern().is_match(&plain) {
+            self.pending.push((line, plain));
+            return output;
+        } else if self.has_primary {
+            // note 和它的包含上下文附加到之前的诊断
+            self.block
+                .extend(self.pending.drain(..).map(|(line, _)| line));
+        } else {
+            self.header.push(plain);
+        }
+        self.block.push(line);
+        output
+    }
+
+    // 输出当前的诊断块, 任意一个主诊断行或者包含上下文匹配时丢弃整个块
+    fn finish_block(&mut self) -> String {
+        let filtered = !self.is_error
+            && self
+                .header
+                .iter()
+                .any(|line| self.filters.iter().any(|f| f.is_match(line)));
+        self.header.clear();
+        self.has_primary = false;
+        self.is_error = false;
+        let block: String = self.block.drain(..).collect();
+        if filtered {
+            String::new()
+        } else {
+            block
+        }
+    }
+
+    pub fn flush(&mut self) -> String {
+        let mut output = self.finish_block();
+        output.extend(self.pending.drain(..).map(|(line, _)| line));
+        output
+    }
+}
+
+// 转发 stderr 的内容并返回原始内容, 有过滤规则时按行处理
+pub fn forward(
+    reader: impl Read,
+    mut writer: impl Write,
+    filter: &mut DiagnosticFilter,
+) -> Vec<u8> {
+    let mut captured = vec![];
+    let mut reader = BufReader::new(reader);
+    if filter.is_empty() {
+        let mut buffer = [0; 4096];
+        while let Ok(n) = reader.read(&mut buffer) {
+            if n == 0 {
+                break;
+            }
+            writer.write_all(&buffer[..n]).unwrap_or(());
+            captured.extend_from_slice(&buffer[..n]);
+        }
+        return captured;
+    }
+    let mut line = vec![];
+    while let Ok(n) = reader.read_until(b'\n', &mut line) {
+        if n == 0 {
+            break;
+        }
+        let output = filter.push(&String::from_utf8_lossy(&line));
+        writer.write_all(output.as_bytes()).unwrap_or(());
+        captured.append(&mut line);
+    }
+    writer.write_all(filter.flush().as_bytes()).unwrap_or(());
+    captured
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_diagnostic_filter() {
+        let stderr = "\
+In file included from main.c:1:
+/opt/sdk/include/sdk.h:3:5: warning: unused variable 'x' [-Wunused-variable]
+    3 |     int x;
+      |         ^
+/tmp/sandbox-1/main.c: In function 'main':
+/tmp/sandbox-1/main.c:4:3: error: 'y' undeclared
+    4 |   y = 1;
+      |   ^
+/tmp/sandbox-1/main.c:4:3: note: each undeclared identifier is reported only once
+/opt/sdk/include/sdk.h:9:1: warning: no newline at end of file
+";
+        let mut filter = DiagnosticFilter::new(
+            &[Regex::new("^/opt/sdk/").unwrap()],
+            &[(
+                Regex::new("/tmp/sandbox-[0-9]+/").unwrap(),
+                "src/".to_string(),
+            )],
+        );
+        let mut output = vec![];
+        let captured = forward(stderr.as_bytes(), &mut output, &mut filter);
+        assert_eq!(captured, stderr.as_bytes());
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "\
+src/main.c: In function 'main':
+src/main.c:4:3: error: 'y' undeclared
+    4 |   y = 1;
+      |   ^
+src/main.c:4:3: note: each undeclared identifier is reported only once
+"
+        );
+
+        // error 的 note 指向过滤的路径时仍然保留, note 和它的包含上下文属于之前的诊断
+        let stderr = "\
+b.c: In function 'f':
+b.c:2:24: error: too many arguments to function 'vendor'
+    2 | int f(void) { return vendor(1); }
+      |                        ^~~~~~
+In file included from b.c:1:
+sdk/v.h:1:6: note: declared here
+    1 | void vendor(void);
+      |      ^~~~~~
+b.c:3:5: warning: unused variable 'y' [-Wunused-variable]
+In file included from b.c:1:
+sdk/v.h:2:5: note: 'x' declared here
+In file included from b.c:1:
+sdk/v.h:3:1: warning: no newline at end of file
+1 warning generated.
+";
+        let mut filter = DiagnosticFilter::new(&[Regex::new("sdk/").unwrap()], &[]);
+        let mut output = vec![];
+        forward(stderr.as_bytes(), &mut output, &mut filter);
+        let expected: String = stderr
+            .lines()
+            .take(11)
+            .map(|l| format!("{}\n", l))
+            .collect();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            expected + "1 warning generated.\n"
+        );
+
+        let mut output = vec![];
+        forward(
+            stderr.as_bytes(),
+            &mut output,
+            &mut DiagnosticFilter::default(),
+        );
+        assert_eq!(output, stderr.as_bytes());
+    }
+}
@@ -0,0 +1,597 @@
+use anyhow::{anyhow, bail, Result};
+use std::collections::HashSet;
+
+const SHT_SYMTAB: u32 = 2;
+const SHT_DYNAMIC: u32 = 6;
+const SHT_DYNSYM: u32 = 11;
+const PT_INTERP: u32 = 3;
+const DT_NULL: usize = 0;
+const DT_NEEDED: usize = 1;
+const DT_RPATH: usize = 15;
+const DT_RUNPATH: usize = 29;
+const SHN_UNDEF: u16 = 0;
+const STB_GLOBAL: u8 = 1;
+const STB_WEAK: u8 = 2;
+const STB_GNU_UNIQUE: u8 = 10;
+
+// 按照 ELF 头中的位数和字节序读取字段
+struct Reader<'a> {
+    data: &'a [u8],
+    is_64: bool,
+    little: bool,
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&self, offset: usize, len: usize) -> Result<&'a [u8]> {
+        match self.data.get(offset..offset.saturating_add(len)) {
+            Some(bytes) => Ok(bytes),
+            None => bail!("truncated elf file"),
+        }
+    }
+
+    fn u8(&self, offset: usize) -> Result<u8> {
+        Ok(self.bytes(offset, 1)?[0])
+    }
+
+    fn u16(&self, offset: usize) -> Result<u16> {
+        let b: [u8; 2] = self.bytes(offset, 2)?.try_into()?;
+        Ok(if self.little {
+            u16::from_le_bytes(b)
+        } else {
+            u16::from_be_bytes(b)
+        })
+    }
+
+    fn u32(&self, offset: usize) -> Result<u32> {
+        let b: [u8; 4] = self.bytes(offset, 4)?.try_into()?;
+        Ok(if self.little {
+            u32::from_le_bytes(b)
+        } else {
+            u32::from_be_bytes(b)
+        })
+    }
+
+    fn u64(&self, offset: usize) -> Result<u64> {
+        let b: [u8; 8] = self.bytes(offset, 8)?.try_into()?;
+        Ok(if self.little {
+            u64::from_le_bytes(b)
+        } else {
+            u64::from_be_bytes(b)
+        })
+    }
+
+    // 32 位 ELF 中地址和偏移为 4 字节, 64 位为 8 字节
+    fn word(&self, offset32: usize, offset64: usize) -> Result<usize> {
+        if self.is_64 {
+            Ok(self.u64(offset64)? as usize)
+        } else {
+            Ok(self.u32(offset32)? as usize)
+        }
+    }
+
+    fn string(&self, offset: usize) -> Result<String> {
+        let rest = match self.data.get(offset..) {
+            Some(rest) => rest,
+            None => bail!("invalid string offset"),
+        };
+        let end = rest.iter().position(|b| *b == 0).unwrap_or(rest.len());
+        Ok(String::from_utf8_lossy(&rest[..end]).into_owned())
+    }
+}
+
+// 偏移来自文件内容, 溢出时视为损坏的文件
+fn add(base: usize, offset: usize) -> Result<usize> {
+    base.checked_add(offset)
+        .ok_or_else(|| anyhow!("invalid elf offset"))
+}
+
+fn entry(base: usize, index: usize, size: usize) -> Result<usize> {
+    index
+        .checked_mul(size)
+        .and_then(|offset| base.checked_add(offset))
+        .ok_or_else(|| anyhow!("invalid elf offset"))
+}
+
+pub struct Section {
+    pub kind: u32,
+    pub offset: usize,
+    pub size: usize,
+    pub link: usize,
+    pub entry_size: usize,
+}
+
+pub struct Symbol {
+    pub name: String,
+    pub defined: bool,
+    pub binding: u8,
+}
+
+impl Symbol {
+    // 可以被其它目标文件引用的符号
+    pub fn is_global(&self) -> bool {
+        matches!(self.binding, STB_GLOBAL | STB_WEAK | STB_GNU_UNIQUE)
+    }
+
+    pub fn is_weak(&self) -> bool {
+        self.binding == STB_WEAK
+    }
+}
+
+// 动态段中的依赖库和搜索路径
+#[derive(Default)]
+pub struct Dynamic {
+    pub needed: Vec<String>,
+    pub rpath: Option<String>,
+    pub runpath: Option<String>,
+}
+
+pub struct Elf<'a> {
+    reader: Reader<'a>,
+    pub sections: Vec<Section>,
+    // PT_* 类型, 文件偏移和大小
+    segments: Vec<(u32, usize, usize)>,
+}
+
+impl<'a> Elf<'a> {
+    pub fn is_elf(data: &[u8]) -> bool {
+        data.starts_with(b"\x7fELF")
+    }
+
+    pub fn parse(data: &'a [u8]) -> Result<Elf<'a>> {
+        if !Elf::is_elf(data) || data.len() < 0x34 {
+            bail!("not an elf file");
+        }
+        let reader = Reader {
+            data,
+            is_64: data[4] == 2,
+            little: data[5] == 1,
+        };
+        let (shoff, shentsize, shnum) = if reader.is_64 {
+            (
+                reader.u64(0x28)? as usize,
+                reader.u16(0x3A)?,
+                reader.u16(0x3C)?,
+            )
+        } else {
+            (
+                reader.u32(0x20)? as usize,
+                reader.u16(0x2E)?,
+                reader.u16(0x30)?,
+            )
+        };
+        let (phoff, phentsize, phnum) = if reader.is_64 {
+            (
+                reader.u64(0x20)? as usize,
+                reader.u16(0x36)?,
+                reader.u16(0x38)?,
+            )
+        } else {
+            (
+                reader.u32(0x1C)? as usize,
+                reader.u16(0x2A)?,
+                reader.u16(0x2C)?,
+            )
+        };
+        let mut segments = vec![];
+        for i in 0..phnum as usize {
+            let base = entry(phoff, i, phentsize as usize)?;
+            segments.push((
+                reader.u32(base)?,
+                reader.word(add(base, 4)?, add(base, 8)?)?,
+                reader.word(add(base, 16)?, add(base, 32)?)?,
+            ));
+        }
+        let mut sections = vec![];
+        for i in 0..shnum as usize {
+            let base = entry(shoff, i, shentsize as usize)?;
+            sections.push(Section {
+                kind: reader.u32(add(base, 4)?)?,
+                offset: reader.word(add(base, 16)?, add(base, 24)?)?,
+                size: reader.word(add(base, 20)?, add(base, 32)?)?,
+                link: reader.u32(add(base, if reader.is_64 { 40 } else { 24 })?)? as usize,
+                entry_size: reader.word(add(base, 36)?, add(base, 56)?)?,
+            });
+        }
+        Ok(Elf {
+            reader,
+            sections,
+            segments,
+        })
+    }
+
+    pub fn is_64(&self) -> bool {
+        self.reader.is_64
+    }
+
+    pub fn machine(&self) -> Result<u16> {
+        self.reader.u16(0x12)
+    }
+
+    // 目标文件的静态符号表, 不包含局部符号
+    pub fn symbols(&self) -> Result<Vec<Symbol>> {
+        self.section_symbols(SHT_SYMTAB)
+    }
+
+    // 静态链接的程序没有动态符号表
+    pub fn has_dynamic_symbols(&self) -> bool {
+        self.sections.iter().any(|s| s.kind == SHT_DYNSYM)
+    }
+
+    // 动态链接使用的符号表
+    pub fn dynamic_symbols(&self) -> Result<Vec<Symbol>> {
+        self.section_symbols(SHT_DYNSYM)
+    }
+
+    fn section_symbols(&self, kind: u32) -> Result<Vec<Symbol>> {
+        let r = &self.reader;
+        let mut symbols = vec![];
+        for section in self.sections.iter().filter(|s| s.kind == kind) {
+            let strtab = match self.sections.get(section.link) {
+                Some(strtab) => strtab.offset,
+                None => bail!("invalid symbol string table"),
+            };
+            let entry_size = if section.entry_size > 0 {
+                section.entry_size
+            } else if r.is_64 {
+                24
+            } else {
+                16
+            };
+            // 第一个符号总是空符号
+            for i in 1..section.size / entry_size {
+                let base = entry(section.offset, i, entry_size)?;
+                let (info, shndx) = if r.is_64 {
+                    (r.u8(add(base, 4)?)?, r.u16(add(base, 6)?)?)
+                } else {
+                    (r.u8(add(base, 12)?)?, r.u16(add(base, 14)?)?)
+                };
+                let symbol = Symbol {
+                    name: r.string(add(strtab, r.u32(base)? as usize)?)?,
+                    defined: shndx != SHN_UNDEF,
+                    binding: info >> 4,
+                };
+                if symbol.is_global() && !symbol.name.is_empty() {
+                    symbols.push(symbol);
+                }
+            }
+        }
+        Ok(symbols)
+    }
+
+    // 程序解释器, 静态链接的程序没有 PT_INTERP
+    pub fn interpreter(&self) -> Result<Option<String>> {
+        match self.segments.iter().find(|s| s.0 == PT_INTERP) {
+            Some(&(_, offset, size)) => {
+                let bytes = self.reader.bytes(offset, size)?;
+                let end = bytes.iter().position(|b| *b == 0).unwrap_or(bytes.len());
+                Ok(Some(String::from_utf8_lossy(&bytes[..end]).into_owned()))
+            }
+            None => Ok(None),
+        }
+    }
+
+    pub fn dynamic(&self) -> Result<Dynamic> {
+        let r = &self.reader;
+        let mut dynamic = Dynamic::default();
+        for section in self.sections.iter().filter(|s| s.kind == SHT_DYNAMIC) {
+            let strtab = match self.sections.get(section.link) {
+                Some(strtab) => strtab.offset,
+                None => bail!("invalid dynamic string table"),
+            };
+            let entry_size = if r.is_64 { 16 } else { 8 };
+            for i in 0..section.size / entry_size {
+                let base = entry(section.offset, i, entry_size)?;
+                let tag = r.word(base, base)?;
+                let value = r.word(add(base, 4)?, add(base, 8)?)?;
+                match tag {
+                    DT_NULL => break,
+                    DT_NEEDED => dynamic.needed.push(r.string(add(strtab, value)?)?),
+                    DT_RPATH => dynamic.rpath = Some(r.string(add(strtab, value)?)?),
+                    DT_RUNPATH => dynamic.runpath = Some(r.string(add(strtab, value)?)?),
+                    _ => {}
+                }
+            }
+        }
+        Ok(dynamic)
+    }
+}
+
+// ar 归档中的成员, 支持 GNU 和 BSD 两种长文件名格式
+pub fn archive_members(data: &[u8]) -> Result<Vec<(String, &[u8])>> {
+    if !data.starts_with(b"!<arch>\n") {
+        bail!("not an ar archive");
+    }
+    let field = |header: &[u8], start: usize, end: usize| {
+        String::from_utf8_lossy(&header[start..end])
+            .trim_end()
+            .to_string()
+    };
+    let mut members = vec![];
+    let mut long_names: &[u8] = &[];
+    let mut offset = 8;
+    while offset + 60 <= data.len() {
+        let header = &data[offset..offset + 60];
+        let name = field(header, 0, 16);
+        let size: usize = match field(header, 48, 58).parse() {
+            Ok(size) => size,
+            Err(_) => bail!("invalid ar member header at {}", offset),
+        };
+        let start = offset + 60;
+        let mut content = match data.get(start..start + size) {
+            Some(content) => content,
+            None => bail!("truncated ar archive"),
+        };
+        // 成员按照 2 字节对齐
+        offset = start + size + size % 2;
+
+        let name = if name == "/" || name == "/SYM64/" || name.starts_with("__.SYMDEF") {
+            continue;
+        } else if name == "//" {
+            long_names = content;
+            continue;
+        } else if let Some(index) = name.strip_prefix('/') {
+            // GNU: /123 表示长文件名表中的偏移
+            let index: usize = index.parse()?;
+            let rest = long_names.get(index..).unwrap_or(&[]);
+            let end = rest.iter().position(|b| *b == b'\n').unwrap_or(rest.len());
+            String::from_utf8_lossy(&rest[..end])
+                .trim_end_matches('/')
+                .to_string()
+        } else if let Some(len) = name.strip_prefix("#1/") {
+            // BSD: #1/20 表示文件名位于内容的前 20 个字节
+            let len: usize = len.parse()?;
+            if len > content.len() {
+                bail!("invalid ar member name");
+            }
+            let name = String::from_utf8_lossy(&content[..len])
+                .trim_end_matches('\0')
+                .to_string();
+            content = &content[len..];
+            if name.starts_with("__.SYMDEF") {
+                continue;
+            }
+            name
+        } else {
+            name.trim_end_matches('/').to_string()
+        };
+        members.push((name, content));
+    }
+    Ok(members)
+}
+
+// 目标文件或者归档文件中定义和引用的全局符号
+#[derive(Default)]
+pub struct SymbolTable {
+    pub defined: HashSet<String>,
+    pub undefined: HashSet<String>,
+}
+
+impl SymbolTable {
+    pub fn read(data: &[u8]) -> Result<SymbolTable> {
+        let mut table = SymbolTable::default();
+        let objects = if Elf::is_elf(data) {
+            vec![data]
+        } else {
+            archive_members(data)?
+                .into_iter()
+                .map(|(_, content)| content)
+                .filter(|content| Elf::is_elf(content))
+                .collect()
+        };
+        for object in objects {
+            for symbol in Elf::parse(object)?.symbols()? {
+                if symbol.defined {
+                    table.defined.insert(symbol.name);
+                } else if !symbol.is_weak() {
+                    // 弱引用不会从归档中拉取成员
+                    table.undefined.insert(symbol.name);
+                }
+            }
+        }
+        // 归档内部已经解决的引用
+        let defined = &table.defined;
+        table.undefined.retain(|s| !defined.contains(s));
+        Ok(table)
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    fn symbol_table(defined: &[&str], undefined: &[&str], strtab: &mut Vec<u8>) -> Vec<u8> {
+        let mut symtab = vec![0u8; 24];
+        for (name, is_defined) in defined
+            .iter()
+            .map(|n| (n, true))
+            .chain(undefined.iter().map(|n| (n, false)))
+        {
+            let mut symbol = [0u8; 24];
+            symbol[..4].copy_from_slice(&(strtab.len() as u32).to_le_bytes());
+            symbol[4] = STB_GLOBAL << 4;
+            symbol[6] = if is_defined { 1 } else { 0 };
+            symtab.extend_from_slice(&symbol);
+            strtab.extend_from_slice(name.as_bytes());
+            strtab.push(0);
+        }
+        symtab
+    }
+
+    // 构造 64 位小端 ELF 文件, sections 为类型, 内容和 sh_link, 索引从 1 开始
+    fn build(sections: &[(u32, Vec<u8>, u32)], interpreter: Option<&str>) -> Vec<u8> {
+        let mut data = vec![0u8; 64];
+        data[..4].copy_from_slice(b"\x7fELF");
+        data[4] = 2;
+        data[5] = 1;
+        if let Some(interpreter) = interpreter {
+            let offset = 64 + 56;
+            let mut header = [0u8; 56];
+            header[..4].copy_from_slice(&PT_INTERP.to_le_bytes());
+            header[8..16].copy_from_slice(&(offset as u64).to_le_bytes());
+            header[32..40].copy_from_slice(&(interpreter.len() as u64 + 1).to_le_bytes());
+            data[0x20..0x28].copy_from_slice(&64u64.to_le_bytes());
+            data[0x36..0x38].copy_from_slice(&56u16.to_le_bytes());
+            data[0x38..0x3A].copy_from_slice(&1u16.to_le_bytes());
+            data.extend_from_slice(&header);
+            data.extend_from_slice(interpreter.as_bytes());
+            data.push(0);
+        }
+        let mut offsets = vec![];
+        for (_, content, _) in sections {
+            offsets.push(data.len());
+            data.extend_from_slice(content);
+        }
+        let shoff = data.len();
+        data[0x28..0x30].copy_from_slice(&(shoff as u64).to_le_bytes());
+        data[0x3A..0x3C].copy_from_slice(&64u16.to_le_bytes());
+        data[0x3C..0x3E].copy_from_slice(&(sections.len() as u16 + 1).to_le_bytes());
+        data.extend_from_slice(&[0u8; 64]);
+        for ((kind, content, link), offset) in sections.iter().zip(offsets) {
+            let mut header = [0u8; 64];
+            header[4..8].copy_from_slice(&kind.to_le_bytes());
+            header[24..32].copy_from_slice(&(offset as u64).to_le_bytes());
+            header[32..40].copy_from_slice(&(content.len() as u64).to_le_bytes());
+            header[40..44].copy_from_slice(&link.to_le_bytes());
+            data.extend_from_slice(&header);
+        }
+        data
+    }
+
+    // 只包含符号表的可重定位目标文件
+    pub fn object(defined: &[&str], undefined: &[&str]) -> Vec<u8> {
+        let mut strtab = vec![0u8];
+        let symtab = symbol_table(defined, undefined, &mut strtab);
+        build(&[(SHT_SYMTAB, symtab, 2), (3, strtab, 0)], None)
+    }
+
+    // 包含动态符号表和动态段的可执行文件或动态库
+    pub fn shared(
+        needed: &[&str],
+        defined: &[&str],
+        undefined: &[&str],
+        interpreter: Option<&str>,
+    ) -> Vec<u8> {
+        let mut dynstr = vec![0u8];
+        let dynsym = symbol_table(defined, undefined, &mut dynstr);
+        let mut dynamic = vec![];
+        for name in needed {
+            dynamic.extend_from_slice(&(DT_NEEDED as u64).to_le_bytes());
+            dynamic.extend_from_slice(&(dynstr.len() as u64).to_le_bytes());
+            dynstr.extend_from_slice(name.as_bytes());
+            dynstr.push(0);
+        }
+        dynamic.extend_from_slice(&[0u8; 16]);
+        build(
+            &[
+                (SHT_DYNSYM, dynsym, 2),
+                (3, dynstr, 0),
+                (SHT_DYNAMIC, dynamic, 2),
+            ],
+            interpreter,
+        )
+    }
+
+    pub fn archive(members: &[(&str, Vec<u8>)]) -> Vec<u8> {
+        let mut data = b"!<arch>\n".to_vec();
+        for (name, content) in members {
+            data.extend_from_slice(
+                format!(
+                    "{:<16}{:<12}{:<6}{:<6}{:<8}{:<10}`\n",
+                    format!("{}/", name),
+                    0,
+                    0,
+                    0,
+                    644,
+                    content.len()
+                )
+                .as_bytes(),
+            );
+            data.extend_from_slice(content);
+            if content.len() % 2 == 1 {
+                data.push(b'\n');
+            }
+        }
+        data
+    }
+
+    #[test]
+    fn test_symbol_table() {
+        let data = archive(&[
+            ("a.o", object(&["foo"], &["bar", "puts"])),
+            ("b.o", object(&["bar"], &["baz"])),
+        ]);
+        let members = archive_members(&data).unwrap();
+        assert_eq!(members.len(), 2);
+        assert_eq!(members[1].0, "b.o");
+
+        let table = SymbolTable::read(&data).unwrap();
+        assert!(table.defined.contains("foo") && table.defined.contains("bar"));
+        let mut undefined: Vec<_> = table.undefined.into_iter().collect();
+        undefined.sort();
+        assert_eq!(undefined, vec!["baz", "puts"]);
+    }
+
+    #[test]
+    fn test_dynamic() {
+        let data = shared(
+            &["libc.so.6", "libm.so.6"],
+            &["main"],
+            &["printf"],
+            Some("/lib64/ld-linux-x86-64.so.2"),
+        );
+        let elf = Elf::parse(&data).unwrap();
+        assert_eq!(
+            elf.interpreter().unwrap().as_deref(),
+            Some("/lib64/ld-linux-x86-64.so.2")
+        );
+        assert_eq!(
+            elf.dynamic().unwrap().needed,
+            vec!["libc.so.6", "libm.so.6"]
+        );
+        let symbols = elf.dynamic_symbols().unwrap();
+        assert!(symbols[0].defined && !symbols[1].defined);
+        assert!(Elf::parse(&object(&[], &[]))
+            .unwrap()
+            .interpreter()
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn test_truncated_header() {
+        let data = object(&["foo"], &[]);
+        assert!(Elf::parse(&data[..40]).is_err());
+        assert!(Elf::parse(&data[..0x38]).is_err());
+        // 节头表在文件末尾, 截断后读取节头失败
+        assert!(Elf::parse(&data[..data.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn test_oversized_offsets() {
+        let data = object(&["foo"], &["bar"]);
+        let shoff = u64::from_le_bytes(data[0x28..0x30].try_into().unwrap()) as usize;
+
+        let mut broken = data.clone();
+        broken[0x28..0x30].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert!(Elf::parse(&broken).is_err());
+
+        let mut broken = data.clone();
+        broken[0x20..0x28].copy_from_slice(&u64::MAX.to_le_bytes());
+        broken[0x36..0x38].copy_from_slice(&56u16.to_le_bytes());
+        broken[0x38..0x3A].copy_from_slice(&2u16.to_le_bytes());
+        assert!(Elf::parse(&broken).is_err());
+
+        // 符号表的文件偏移
+        let mut broken = data.clone();
+        let offset = shoff + 64 + 24;
+        broken[offset..offset + 8].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert!(Elf::parse(&broken).unwrap().symbols().is_err());
+
+        // 字符串表的文件偏移
+        let mut broken = data.clone();
+        let offset = shoff + 2 * 64 + 24;
+        broken[offset..offset + 8].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert!(Elf::parse(&broken).unwrap().symbols().is_err());
+        assert!(SymbolTable::read(&broken).is_err());
+    }
+}
@@ -0,0 +1,123 @@
+use anyhow::{bail, Result};
+use simplelog::{debug, warn};
+use std::process::Command;
+
+use crate::{Configuration, ResponseFile};
+
+pub enum Phase {
+    Pre,
+    // 命令的退出码
+    Post(i32),
+}
+
+fn shell(hook: &str) -> Command {
+    if cfg!(windows) {
+        let mut command = Command::new("cmd");
+        command.arg("/C").arg(hook);
+        command
+    } else {
+        let mut command = Command::new("sh");
+        command.arg("-c").arg(hook);
+        command
+    }
+}
+
+fn run_hook(config: &Configuration, hook: &str, phase: &Phase) -> Result<()> {
+    // 参数使用 ResponseFile 的转义规则拼接
+    let argv: Vec<String> = [&config.command]
+        .into_iter()
+        .chain(config.arguments.iter())
+        .map(|arg| ResponseFile::escape(arg))
+        .collect();
+    let mut command = shell(hook);
+    for key in config.removed_environment.iter() {
+        command.env_remove(key);
+    }
+    command.envs(config.environment.iter().map(|(k, v)| (k, v)));
+    command.env("CLW_HOOK_COMMAND", &config.command);
+    command.env("CLW_HOOK_ARGV", argv.join(" "));
+    command.env("CLW_HOOK_OUTPUT", config.output_file().unwrap_or_default());
+    match phase {
+        Phase::Pre => {
+            command.env("CLW_HOOK_PHASE", "pre");
+        }
+        Phase::Post(code) => {
+            command.env("CLW_HOOK_PHASE", "post");
+            command.env("CLW_HOOK_EXIT_CODE", code.to_string());
+        }
+    }
+    if !config.work_dir.is_empty() {
+        command.current_dir(&config.work_dir);
+    }
+
+    debug!("running hook: {}", hook);
+    let status = command.status()?;
+    if !status.success() {
+        bail!(
+            "hook '{}' exited with {}",
+            hook,
+            status.code().unwrap_or(-1)
+        );
+    }
+    Ok(())
+}
+
+// 依次运行钩子, 失败时停止运行后续的钩子, 设置 -clw-hook-ignore-failure 时只输出警告
+pub fn run_hooks(config: &Configuration, phase: Phase) -> Result<()> {
+    let hooks = match phase {
+        Phase::Pre => &config.pre_hooks,
+        Phase::Post(code) if code != 0 && !config.post_hook_always => return Ok(()),
+        Phase::Post(_) => &config.post_hooks,
+    };
+    for hook in hooks {
+        if let Err(e) = run_hook(config, hook, &phase) {
+            if !config.hook_ignore_failure {
+                return Err(e);
+            }
+            warn!("{}", e);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs;
+
+    #[test]
+    fn test_run_hooks() {
+        let dir = env::temp_dir().join(format!("clw_hook_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let mut config = Configuration::new();
+        config.command = "gcc".to_owned();
+        config.work_dir = dir.to_string_lossy().into_owned();
+        config.arguments = ["-o", "a b", "a.c"].map(String::from).to_vec();
+        config.pre_hooks = vec!["echo \"$CLW_HOOK_PHASE $CLW_HOOK_ARGV\" > pre.txt".to_owned()];
+        config.post_hooks = vec![
+            "echo \"$CLW_HOOK_EXIT_CODE $CLW_HOOK_OUTPUT\" > post.txt".to_owned(),
+            "exit 3".to_owned(),
+        ];
+
+        run_hooks(&config, Phase::Pre).unwrap();
+        assert_eq!(
+            fs::read_to_string(dir.join("pre.txt")).unwrap(),
+            "pre gcc -o \"a b\" a.c\n"
+        );
+
+        // 默认只在命令成功时运行 post 钩子
+        run_hooks(&config, Phase::Post(1)).unwrap();
+        assert!(!dir.join("post.txt").exists());
+        let error = run_hooks(&config, Phase::Post(0)).unwrap_err();
+        assert!(error.to_string().contains("exited with 3"));
+        assert_eq!(fs::read_to_string(dir.join("post.txt")).unwrap(), "0 a b\n");
+
+        config.post_hook_always = true;
+        config.hook_ignore_failure = true;
+        run_hooks(&config, Phase::Post(1)).unwrap();
+        assert_eq!(fs::read_to_string(dir.join("post.txt")).unwrap(), "1 a b\n");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
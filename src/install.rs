@@ -0,0 +1,185 @@
+use anyhow::{bail, Result};
+use std::env;
+use std::fs;
+use std::path::{self, Path, PathBuf};
+
+use cli_wrapper::report::tool_name;
+use cli_wrapper::tool::find_program;
+
+const INSTALL_USAGE: &str = "\
+Usage: cli-wrapper install --tools <tool,...> --dir <bin dir> --real-dir <dir> [--force]
+
+Create symlinks named after the tools in <bin dir> pointing to cli-wrapper. When invoked
+through a symlink, the tool name selects <bin dir>/<tool>-clw-config.txt, which is created
+with -clw-command=<real dir>/<tool> so that the real tool gets executed.
+
+Options:
+  --tools <tool,...> Comma separated tool names, e.g. gcc,g++,ld
+  --dir <dir>        Directory of the symlinks, usually put in front of PATH
+  --real-dir <dir>   Directory containing the real tools
+  --force            Replace existing files in <bin dir>
+  -h, --help         Print this help";
+
+fn same_file(a: &Path, b: &Path) -> bool {
+    match (fs::canonicalize(a), fs::canonicalize(b)) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false,
+    }
+}
+
+// 通过符号链接调用时返回调用的路径, 和 busybox 一样使用 argv[0] 区分工具
+pub fn invoked_path(exe: &str) -> Option<String> {
+    let argv0 = env::args().next()?;
+    let name = tool_name(&argv0);
+    if name.is_empty() || name == tool_name(exe) {
+        return None;
+    }
+    let path = if argv0.contains(['/', '\\']) {
+        path::absolute(&argv0).ok()?
+    } else {
+        find_program(&argv0)?
+    };
+    Some(path.to_string_lossy().into_owned())
+}
+
+#[cfg(unix)]
+fn create_link(exe: &Path, link: &Path) -> std::io::Result<()> {
+    std::os::unix::fs::symlink(exe, link)
+}
+
+#[cfg(windows)]
+fn create_link(exe: &Path, link: &Path) -> std::io::Result<()> {
+    std::os::windows::fs::symlink_file(exe, link)
+}
+
+fn install_tools(
+    tools: &[&str],
+    dir: &Path,
+    real_dir: &Path,
+    force: bool,
+    exe: &Path,
+) -> Result<()> {
+    fs::create_dir_all(dir)?;
+    let real_dir = path::absolute(real_dir)?;
+    for tool in tools {
+        let name = if cfg!(windows) {
+            format!("{}.exe", tool)
+        } else {
+            tool.to_string()
+        };
+        let real = real_dir.join(&name);
+        if !real.is_file() {
+            bail!("real tool {} does not exist", real.to_string_lossy());
+        }
+        if same_file(&real, exe) {
+            bail!(
+                "{} is cli-wrapper itself, refusing to wrap it recursively",
+                real.to_string_lossy()
+            );
+        }
+
+        let link = dir.join(&name);
+        if fs::symlink_metadata(&link).is_ok() {
+            if !force && !same_file(&link, exe) {
+                bail!(
+                    "{} already exists, use --force to replace it",
+                    link.to_string_lossy()
+                );
+            }
+            fs::remove_file(&link)?;
+        }
+        create_link(exe, &link)?;
+
+        // 保留配置文件中已有的规则, 只更新真实工具的路径
+        let config = dir.join(format!("{}-clw-config.txt", tool));
+        let content = fs::read_to_string(&config).unwrap_or_default();
+        let mut lines = vec![format!("-clw-command={}", real.to_string_lossy())];
+        lines.extend(
+            content
+                .lines()
+                .filter(|line| !line.starts_with("-clw-command="))
+                .map(|line| line.to_string()),
+        );
+        fs::write(&config, lines.join("\n") + "\n")?;
+        println!(
+            "installed {} -> {}",
+            link.to_string_lossy(),
+            real.to_string_lossy()
+        );
+    }
+    Ok(())
+}
+
+pub fn install_command(args: impl Iterator<Item = String>) -> Result<i32> {
+    let mut tools = String::new();
+    let mut dir = String::new();
+    let mut real_dir = String::new();
+    let mut force = false;
+
+    let mut args = args;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-h" | "--help" => {
+                println!("{}", INSTALL_USAGE);
+                return Ok(0);
+            }
+            "--tools" => tools = args.next().unwrap_or_default(),
+            "--dir" => dir = args.next().unwrap_or_default(),
+            "--real-dir" => real_dir = args.next().unwrap_or_default(),
+            "--force" => force = true,
+            _ => bail!("unknown install option '{}'", arg),
+        }
+    }
+    let tools: Vec<&str> = tools.split(',').filter(|t| !t.is_empty()).collect();
+    if tools.is_empty() || dir.is_empty() || real_dir.is_empty() {
+        eprintln!("{}", INSTALL_USAGE);
+        return Ok(1);
+    }
+    let exe = fs::canonicalize(env::current_exe()?)?;
+    install_tools(
+        &tools,
+        Path::new(&dir),
+        &PathBuf::from(real_dir),
+        force,
+        &exe,
+    )?;
+    Ok(0)
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_install_tools() {
+        let root = env::temp_dir().join(format!("clw_install_{}", std::process::id()));
+        let (bin, real) = (root.join("bin"), root.join("real"));
+        fs::create_dir_all(&real).unwrap();
+        fs::write(real.join("gcc"), "").unwrap();
+        fs::write(root.join("cli-wrapper"), "").unwrap();
+        let exe = fs::canonicalize(root.join("cli-wrapper")).unwrap();
+        fs::create_dir_all(&bin).unwrap();
+        fs::write(
+            bin.join("gcc-clw-config.txt"),
+            "-clw-command=old\n-clw-remove=-lm\n",
+        )
+        .unwrap();
+
+        install_tools(&["gcc"], &bin, &real, false, &exe).unwrap();
+        assert_eq!(fs::read_link(bin.join("gcc")).unwrap(), exe);
+        assert_eq!(
+            fs::read_to_string(bin.join("gcc-clw-config.txt")).unwrap(),
+            format!(
+                "-clw-command={}\n-clw-remove=-lm\n",
+                real.join("gcc").to_string_lossy()
+            )
+        );
+        // 重复安装会更新已有的符号链接
+        install_tools(&["gcc"], &bin, &real, false, &exe).unwrap();
+
+        let error = install_tools(&["gcc"], &root, &bin, true, &exe).unwrap_err();
+        assert!(error.to_string().contains("recursively"));
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,1432 @@
+pub mod argument;
+pub mod atomic;
+pub mod cache;
+pub mod cmdline;
+pub mod depfile;
+pub mod diagnostic;
+pub mod elf;
+pub mod hook;
+mod link;
+pub mod output;
+pub mod report;
+mod rpath;
+pub mod rule;
+#[cfg(feature = "script")]
+mod script;
+pub mod status;
+pub mod tool;
+pub mod verify;
+
+use anyhow::Result;
+use argument::{Argument, ToolKind};
+pub use rule::{CommandType, Registry, Rule};
+use simplelog::*;
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+pub struct ResponseFile {
+    pub original_path: String,
+    pub new_path: String,
+    pub values: Vec<String>,
+    pub changed: bool,
+}
+
+impl ResponseFile {
+    /// 读取 original_path 中的参数, 修改后的参数写入 new_path, 读取失败时参数为空
+    pub fn new(original_path: String, new_path: String) -> ResponseFile {
+        ResponseFile {
+            original_path: original_path.clone(),
+            new_path,
+            values: Self::read(&original_path).unwrap_or(vec![]),
+            changed: false,
+        }
+    }
+
+    /// 删除所有等于 value 的参数
+    pub fn remove_value(&mut self, value: &str) {
+        let old = self.values.len();
+        self.values.retain(|v| v != value);
+        self.changed |= old != self.values.len();
+    }
+
+    /// 把所有等于 old 的参数替换为 new
+    pub fn replace_value(&mut self, old: &str, new: &str) {
+        for arg in self.values.iter_mut() {
+            if arg == old {
+                *arg = new.to_string();
+                self.changed = true;
+            }
+        }
+    }
+
+    /// 读取并解析 @file 中的参数
+    pub fn read(path: &str) -> Result<Vec<String>> {
+        let content = fs::read_to_string(path)?.replace("\r\n", "\n");
+        Ok(Self::parse(&content))
+    }
+
+    /// 把参数转义后写入 new_path
+    pub fn write(&self) -> Result<()> {
+        let content: String = self
+            .values
+            .iter()
+            .map(|v| Self::escape(v))
+            .collect::<Vec<String>>()
+            .join(" ");
+
+        fs::write(&self.new_path, content)?;
+        Ok(())
+    }
+
+    /// 按照 @file 的格式转义单个参数, 包含空白或者引号时加上双引号
+    pub fn escape(arg: &str) -> String {
+        let mut result = String::new();
+        let mut needs_quotes = false;
+
+        for c in arg.chars() {
+            match c {
+                ' ' => {
+                    needs_quotes = true;
+                    result.push(c);
+                }
+                '\t' => {
+                    needs_quotes = true;
+                    result.push_str("\\t");
+                }
+                '"' => {
+                    needs_quotes = true;
+                    result.push_str("\\\"");
+                }
+                '\\' => {
+                    result.push_str("\\\\");
+                }
+                _ => {
+                    result.push(c);
+                }
+            }
+        }
+
+        if needs_quotes {
+            format!("\"{}\"", result)
+        } else {
+            result
+        }
+    }
+
+    /// 还原 escape 转义的反斜杠和引号
+    pub fn unescape(arg: &str) -> String {
+        let mut result = String::new();
+        let mut chars = arg.chars();
+        while let Some(c) = chars.next() {
+            if c == '\\' {
+                if let Some(next_char) = chars.next() {
+                    match next_char {
+                        '"' => result.push('"'),
+                        '\\' => result.push('\\'),
+                        _ => {
+                            result.push('\\');
+                            result.push(next_char);
+                        }
+                    }
+                } else {
+                    result.push('\\');
+                }
+            } else {
+                result.push(c);
+            }
+        }
+        result
+    }
+
+    /// 把 @file 的内容拆分为参数, 支持双引号和反斜杠转义
+    pub fn parse(content: &str) -> Vec<String> {
+        let mut args = Vec::new();
+        let mut current_arg = String::new();
+        let mut in_quotes = false;
+        let mut escape_next = false;
+
+        for c in content.chars() {
+            if escape_next {
+                current_arg.push(c);
+                escape_next = false;
+            } else if c == '\\' {
+                escape_next = true;
+            } else if c == '"' {
+                in_quotes = !in_quotes;
+            } else if c.is_whitespace() && !in_quotes {
+                if !current_arg.is_empty() {
+                    args.push(Self::unescape(&current_arg));
+                    current_arg.clear();
+                }
+            } else {
+                current_arg.push(c);
+            }
+        }
+
+        if !current_arg.is_empty() {
+            args.push(Self::unescape(&current_arg));
+        }
+
+        args
+    }
+}
+
+pub struct Configuration {
+    pub command: String,
+    pub work_dir: String,
+    pub just_print: bool,
+    pub before_print: bool,
+    pub redirect_stdout: String,
+    pub redirect_stderr: String,
+    pub arguments: Vec<String>,
+    pub response_map: HashMap<String, ResponseFile>,
+    pub log_file: String,
+    pub json_log: String,
+    pub cache_dir: String,
+    pub cache_max_size: u64,
+    pub prefix_maps: Vec<(String, String)>,
+    pub environment: Vec<(String, String)>,
+    // 执行命令前删除的环境变量
+    pub removed_environment: Vec<String>,
+    pub verifications: Vec<verify::Verification>,
+    pub explain: bool,
+    pub script_allow_write: bool,
+    pub pre_hooks: Vec<String>,
+    pub post_hooks: Vec<String>,
+    pub hook_ignore_failure: bool,
+    pub post_hook_always: bool,
+    pub stderr_filters: Vec<regex::Regex>,
+    pub stderr_rewrites: Vec<(regex::Regex, String)>,
+    pub exit_rules: Vec<status::ExitRule>,
+    pub output_steps: Vec<output::OutputStep>,
+    pub atomic_output: bool,
+    pub depfile_rewrites: Vec<(String, String)>,
+    pub depfile_relative: bool,
+    // 0 表示使用平台的限制
+    pub max_command_line: u64,
+}
+
+impl Configuration {
+    pub fn new() -> Configuration {
+        // 部分配置可以从环境变量读取默认值
+        Configuration {
+            command: get_string_environment_variable("CLW_OPT_COMMAND"),
+            work_dir: "".to_string(),
+            just_print: have_bool_environment_variable("CLW_OPT_JUST_PRINT"),
+            before_print: have_bool_environment_variable("CLW_OPT_BEFORE_PRINT"),
+            redirect_stdout: get_string_environment_variable("CLW_OPT_REDIRECT_STDOUT"),
+            redirect_stderr: get_string_environment_variable("CLW_OPT_REDIRECT_STDERR"),
+            arguments: vec![],
+            response_map: HashMap::new(),
+            log_file: get_string_environment_variable("CLW_LOG_FILE"),
+            json_log: get_string_environment_variable("CLW_JSON_LOG"),
+            cache_dir: get_string_environment_variable("CLW_CACHE_DIR"),
+            cache_max_size: cache::parse_size(&get_string_environment_variable(
+                "CLW_CACHE_MAX_SIZE",
+            ))
+            .unwrap_or(cache::DEFAULT_CACHE_MAX_SIZE),
+            prefix_maps: vec![],
+            environment: vec![],
+            removed_environment: vec![],
+            verifications: vec![],
+            explain: false,
+            script_allow_write: false,
+            pre_hooks: vec![],
+            post_hooks: vec![],
+            hook_ignore_failure: false,
+            post_hook_always: false,
+            stderr_filters: vec![],
+            stderr_rewrites: vec![],
+            exit_rules: vec![],
+            output_steps: vec![],
+            atomic_output: false,
+            depfile_rewrites: vec![],
+            depfile_relative: false,
+            max_command_line: 0,
+        }
+    }
+
+    pub fn replace_response_file(&mut self) -> Result<()> {
+        // 不支持嵌套 ResponseFile
+        for (_, v) in self.response_map.iter() {
+            if v.changed {
+                v.write()?;
+                let before = format!("@{}", v.original_path);
+                let after = format!("@{}", v.new_path);
+
+                for arg in self.arguments.iter_mut() {
+                    if arg == &before {
+                        *arg = after.clone();
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // 将 ResponseFile 展开后的完整参数
+    pub fn expanded_arguments(&self) -> Vec<String> {
+        let mut result = vec![];
+        for arg in self.arguments.iter() {
+            let response = arg.strip_prefix("@").and_then(|path| {
+                self.response_map
+                    .values()
+                    .find(|res| res.original_path == path || res.new_path == path)
+            });
+            match response {
+                Some(res) => result.extend(res.values.iter().cloned()),
+                None => result.push(arg.clone()),
+            }
+        }
+        result
+    }
+
+    pub fn output_file(&self) -> Option<String> {
+        argument::find_output(self.tool_kind(), &self.expanded_arguments())
+    }
+
+    // 输出文件的绝对路径, 没有指定时使用编译器默认的 a.out
+    pub fn output_path(&self) -> Result<PathBuf> {
+        let output = self.output_file().unwrap_or_else(|| "a.out".to_string());
+        self.resolve_path(&output)
+    }
+
+    // 相对路径相对于命令的工作目录
+    pub fn resolve_path(&self, path: &str) -> Result<PathBuf> {
+        let base = if self.work_dir.is_empty() {
+            env::current_dir()?
+        } else {
+            PathBuf::from(&self.work_dir)
+        };
+        Ok(base.join(path))
+    }
+
+    pub fn tool_kind(&self) -> ToolKind {
+        ToolKind::detect(&self.command)
+    }
+}
+
+impl Default for Configuration {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for Configuration {
+    fn drop(&mut self) {
+        self.response_map
+            .values()
+            .map(|f| fs::remove_file(&f.new_path).unwrap_or(()))
+            .count();
+    }
+}
+
+// 链接器当前的静态/动态链接等状态, --push-state 保存的状态使用栈记录
+#[derive(Clone)]
+struct LinkState {
+    dynamic: bool,
+    whole_archive: bool,
+    group: bool,
+    stack: Vec<(bool, bool)>,
+}
+
+impl LinkState {
+    fn new() -> LinkState {
+        LinkState {
+            dynamic: true,
+            whole_archive: false,
+            group: false,
+            stack: vec![],
+        }
+    }
+
+    // 处理单个链接器参数, -Wl, 和 -Xlinker 的参数需要先拆分
+    fn apply(&mut self, arg: &str) {
+        match arg {
+            "-Bstatic" | "-dn" | "-non_shared" | "-static" => self.dynamic = false,
+            "-Bdynamic" | "-dy" | "-call_shared" => self.dynamic = true,
+            "--whole-archive" | "-whole-archive" => self.whole_archive = true,
+            "--no-whole-archive" | "-no-whole-archive" => self.whole_archive = false,
+            "--start-group" | "-start-group" | "-(" => self.group = true,
+            "--end-group" | "-end-group" | "-)" => self.group = false,
+            "--push-state" | "-push-state" => self.stack.push((self.dynamic, self.whole_archive)),
+            "--pop-state" | "-pop-state" => {
+                if let Some((dynamic, whole_archive)) = self.stack.pop() {
+                    self.dynamic = dynamic;
+                    self.whole_archive = whole_archive;
+                }
+            }
+            // -Bshareable 等同于 -shared, --as-needed 只影响 DT_NEEDED, 都不改变静态/动态状态
+            _ => {}
+        }
+    }
+
+    fn apply_argument(&mut self, kind: ToolKind, arg: &Argument, tokens: &[String]) {
+        if kind == ToolKind::Compiler && arg.is("-Wl,") {
+            for value in arg.value.as_deref().unwrap_or("").split(',') {
+                self.apply(value);
+            }
+        } else if kind == ToolKind::Compiler && arg.is("-Xlinker") {
+            self.apply(arg.value.as_deref().unwrap_or(""));
+        } else if tokens.len() == 1 {
+            self.apply(&tokens[0]);
+        }
+    }
+}
+
+// 链接器参数在编译器中需要使用 -Wl, 传递
+fn link_spelling(kind: ToolKind, arg: &str) -> String {
+    if kind == ToolKind::Compiler {
+        format!("-Wl,{}", arg)
+    } else {
+        arg.to_string()
+    }
+}
+
+// 插入的链接器开关, 如 -Bdynamic/-Bstatic, target 为目标库需要的状态
+struct LinkToggle {
+    on: &'static str,
+    off: &'static str,
+    target: bool,
+    get: fn(&LinkState) -> bool,
+}
+
+impl LinkToggle {
+    fn open(&self) -> &'static str {
+        if self.target {
+            self.on
+        } else {
+            self.off
+        }
+    }
+
+    fn close(&self) -> &'static str {
+        if self.target {
+            self.off
+        } else {
+            self.on
+        }
+    }
+
+    // 之后的参数会重新设置状态, 不需要恢复
+    fn overrides(&self, value: &str) -> bool {
+        value == self.on || value == self.off || matches!(value, "--pop-state" | "-pop-state")
+    }
+}
+
+// 按照链接器参数拆分后的单元, -Wl,a,b 中的每个值单独处理
+enum LinkUnit {
+    Tokens(Vec<String>),
+    // 所在的 -Wl, 参数的序号和值
+    Value(usize, String),
+    Inserted(&'static str),
+    Removed,
+}
+
+// 连续的目标库共用一个区域, 只在状态不同时插入开关
+struct LinkWrapper<'a> {
+    toggle: &'a LinkToggle,
+    state: &'a mut LinkState,
+    units: Vec<LinkUnit>,
+    wrapped: Vec<String>,
+    // 处于插入的区域中, state 仍然记录区域之外的状态
+    open: bool,
+    // 上一个单元是单独的开关时记录它的位置和之前的状态
+    last_toggle: Option<(usize, LinkState)>,
+}
+
+impl LinkWrapper<'_> {
+    // value 为单元对应的链接器参数, target 为匹配的目标库
+    fn push(&mut self, unit: LinkUnit, value: Option<&str>, target: Option<&str>) {
+        let toggle = self.toggle;
+        if let Some(target) = target {
+            if self.open || (toggle.get)(self.state) != toggle.target {
+                if !self.open {
+                    // 紧邻的开关会被插入的开关覆盖, 直接删除
+                    if let Some((index, state)) = self.last_toggle.take() {
+                        self.units[index] = LinkUnit::Removed;
+                        *self.state = state;
+                    }
+                    if (toggle.get)(self.state) != toggle.target {
+                        self.units.push(LinkUnit::Inserted(toggle.open()));
+                    }
+                    self.open = true;
+                }
+                if !self.wrapped.iter().any(|w| w == target) {
+                    self.wrapped.push(target.to_string());
+                }
+                self.units.push(unit);
+                return;
+            }
+        }
+        if self.open {
+            self.open = false;
+            match value {
+                // 切换到相同的状态, 区域直接延续到这里
+                Some(value) if value == toggle.open() => {
+                    self.state.apply(value);
+                    self.last_toggle = None;
+                    return;
+                }
+                Some(value) if toggle.overrides(value) => {}
+                _ => self.close(),
+            }
+        }
+        self.last_toggle = value
+            .filter(|v| *v == toggle.on || *v == toggle.off)
+            .map(|_| (self.units.len(), self.state.clone()));
+        if let Some(value) = value {
+            self.state.apply(value);
+        }
+        self.units.push(unit);
+    }
+
+    fn close(&mut self) {
+        self.units.push(LinkUnit::Inserted(self.toggle.close()));
+        self.state.apply(self.toggle.close());
+    }
+
+    // 重新组合参数, 位于同一个 -Wl, 参数的两个值之间的开关作为它的值
+    fn finish(mut self, kind: ToolKind) -> (Vec<String>, Vec<String>) {
+        if self.open {
+            self.close();
+        }
+        let mut result = vec![];
+        let mut group: Option<(usize, Vec<String>)> = None;
+        let flush = |group: &mut Option<(usize, Vec<String>)>, result: &mut Vec<String>| {
+            if let Some((_, values)) = group.take() {
+                result.push(format!("-Wl,{}", values.join(",")));
+            }
+        };
+        for (i, unit) in self.units.iter().enumerate() {
+            match unit {
+                LinkUnit::Value(index, value) => {
+                    if group.as_ref().map(|(g, _)| g) != Some(index) {
+                        flush(&mut group, &mut result);
+                        group = Some((*index, vec![]));
+                    }
+                    group.as_mut().unwrap().1.push(value.clone());
+                }
+                LinkUnit::Inserted(value) => {
+                    let next = self.units[i + 1..]
+                        .iter()
+                        .find(|u| !matches!(u, LinkUnit::Removed));
+                    match (&mut group, next) {
+                        (Some((g, values)), Some(LinkUnit::Value(n, _))) if g == n => {
+                            values.push(value.to_string())
+                        }
+                        _ => {
+                            flush(&mut group, &mut result);
+                            result.push(link_spelling(kind, value));
+                        }
+                    }
+                }
+                LinkUnit::Tokens(tokens) => {
+                    flush(&mut group, &mut result);
+                    result.extend(tokens.iter().cloned());
+                }
+                LinkUnit::Removed => {}
+            }
+        }
+        flush(&mut group, &mut result);
+        (result, self.wrapped)
+    }
+}
+
+// 跟踪链接状态, 将匹配 targets 的参数切换到 toggle 的目标状态, 返回切换过的目标
+fn wrap_link_argument(
+    targets: &[String],
+    kind: ToolKind,
+    toggle: &LinkToggle,
+    state: &mut LinkState,
+    arguments: &mut Vec<String>,
+    response_map: &mut HashMap<String, ResponseFile>,
+) -> Vec<String> {
+    let options: Vec<_> = targets
+        .iter()
+        .map(|t| argument::parse_option(kind, t))
+        .collect();
+    let mut wrapper = LinkWrapper {
+        toggle,
+        state,
+        units: vec![],
+        wrapped: vec![],
+        open: false,
+        last_toggle: None,
+    };
+    for arg in argument::parse(kind, arguments) {
+        let tokens = arg.tokens(arguments);
+        if kind == ToolKind::Compiler && arg.is("-Wl,") {
+            // -Wl,-Bstatic,-lfoo,-Bdynamic 需要逐个处理链接器参数
+            for value in arg.value.as_deref().unwrap_or("").split(',') {
+                let target = targets.iter().find(|t| *t == value);
+                wrapper.push(
+                    LinkUnit::Value(arg.index, value.to_string()),
+                    Some(value),
+                    target.map(|t| t.as_str()),
+                );
+            }
+        } else if let Some(path) = tokens[0].strip_prefix("@") {
+            wrapper.push(LinkUnit::Tokens(tokens.to_vec()), None, None);
+            if let Some(res) = response_map.get_mut(path) {
+                let wrapped = wrap_link_argument(
+                    targets,
+                    kind,
+                    toggle,
+                    wrapper.state,
+                    &mut res.values,
+                    // 不支持嵌套 ResponseFile
+                    &mut HashMap::new(),
+                );
+                res.changed |= !wrapped.is_empty();
+                for target in wrapped {
+                    if !wrapper.wrapped.contains(&target) {
+                        wrapper.wrapped.push(target);
+                    }
+                }
+            }
+        } else {
+            let target = targets.iter().zip(options.iter()).find(|(t, o)| {
+                (tokens.len() == 1 && tokens[0] == **t)
+                    || o.as_ref().is_some_and(|(o, v)| arg.matches(o, v))
+            });
+            let value = if kind == ToolKind::Compiler && arg.is("-Xlinker") {
+                arg.value.as_deref()
+            } else if tokens.len() == 1 {
+                Some(tokens[0].as_str())
+            } else {
+                None
+            };
+            wrapper.push(
+                LinkUnit::Tokens(tokens.to_vec()),
+                value,
+                target.map(|(t, _)| t.as_str()),
+            );
+        }
+    }
+    let (result, wrapped) = wrapper.finish(kind);
+    *arguments = result;
+    wrapped
+}
+
+fn change_link_feature(
+    targets: &[String],
+    kind: ToolKind,
+    dynamic_link: bool,
+    state: &mut LinkState,
+    arguments: &mut Vec<String>,
+    response_map: &mut HashMap<String, ResponseFile>,
+) -> Vec<String> {
+    // 更改链接方式但不更改链接顺序, 因为有些情况下链接顺序很重要
+    let toggle = LinkToggle {
+        on: "-Bdynamic",
+        off: "-Bstatic",
+        target: dynamic_link,
+        get: |state| state.dynamic,
+    };
+    wrap_link_argument(targets, kind, &toggle, state, arguments, response_map)
+}
+
+fn static_link_feature(key: String, kind: ToolKind, arg: &mut Configuration) -> Result<()> {
+    change_link_feature(
+        &[key],
+        kind,
+        false,
+        &mut LinkState::new(),
+        &mut arg.arguments,
+        &mut arg.response_map,
+    );
+    Ok(())
+}
+
+fn dynamic_link_feature(key: String, kind: ToolKind, arg: &mut Configuration) -> Result<()> {
+    change_link_feature(
+        &[key],
+        kind,
+        true,
+        &mut LinkState::new(),
+        &mut arg.arguments,
+        &mut arg.response_map,
+    );
+    Ok(())
+}
+
+// 在所有 -l 参数中查找需要转换链接方式的库, allowlist 中的库保持不变
+fn convert_all_link_feature(
+    allowlist: String,
+    kind: ToolKind,
+    dynamic_link: bool,
+    arg: &mut Configuration,
+) {
+    let allowed: Vec<&str> = allowlist
+        .split(',')
+        .map(|l| l.trim())
+        .map(|l| l.strip_prefix("-l").unwrap_or(l))
+        .filter(|l| !l.is_empty())
+        .collect();
+    let mut libraries: Vec<String> = vec![];
+    for parsed in argument::parse(kind, &arg.expanded_arguments()) {
+        let mut names = vec![];
+        if parsed.is("-l") {
+            names.push(parsed.value.unwrap_or_default());
+        } else if kind == ToolKind::Compiler && parsed.is("-Wl,") {
+            names.extend(
+                parsed
+                    .value
+                    .unwrap_or_default()
+                    .split(',')
+                    .filter_map(|v| v.strip_prefix("-l").map(|v| v.to_string())),
+            );
+        }
+        for name in names {
+            if !name.is_empty() && !libraries.contains(&name) {
+                libraries.push(name);
+            }
+        }
+    }
+
+    let (kept, targets): (Vec<String>, Vec<String>) = libraries
+        .into_iter()
+        .partition(|name| allowed.contains(&name.as_str()));
+    let kept: Vec<String> = kept.iter().map(|name| format!("-l{}", name)).collect();
+    let targets: Vec<String> = targets.iter().map(|name| format!("-l{}", name)).collect();
+    // 一次处理全部的库, 相邻的库共用一个区域
+    let converted = change_link_feature(
+        &targets,
+        kind,
+        dynamic_link,
+        &mut LinkState::new(),
+        &mut arg.arguments,
+        &mut arg.response_map,
+    );
+    let mode = if dynamic_link { "dynamic" } else { "static" };
+    if !converted.is_empty() {
+        info!("converted to {} link: {}", mode, converted.join(" "));
+    }
+    if !kept.is_empty() {
+        info!("kept by the {} link allowlist: {}", mode, kept.join(" "));
+    }
+}
+
+fn all_static_feature(allowlist: String, kind: ToolKind, arg: &mut Configuration) -> Result<()> {
+    convert_all_link_feature(allowlist, kind, false, arg);
+    Ok(())
+}
+
+fn all_dynamic_feature(allowlist: String, kind: ToolKind, arg: &mut Configuration) -> Result<()> {
+    convert_all_link_feature(allowlist, kind, true, arg);
+    Ok(())
+}
+
+fn whole_archive_feature(key: String, kind: ToolKind, arg: &mut Configuration) -> Result<()> {
+    let toggle = LinkToggle {
+        on: "--whole-archive",
+        off: "--no-whole-archive",
+        target: true,
+        get: |state| state.whole_archive,
+    };
+    wrap_link_argument(
+        &[key],
+        kind,
+        &toggle,
+        &mut LinkState::new(),
+        &mut arg.arguments,
+        &mut arg.response_map,
+    );
+    Ok(())
+}
+
+// 将所有成员移动到最后一个成员的位置并使用 --start-group/--end-group 包裹,
+// 每个 ResponseFile 中的成员单独分组
+fn group_link_arguments(
+    members: &[&str],
+    kind: ToolKind,
+    arguments: &mut Vec<String>,
+    response_map: &mut HashMap<String, ResponseFile>,
+) -> bool {
+    let options: Vec<_> = members
+        .iter()
+        .map(|m| argument::parse_option(kind, m))
+        .collect();
+    let mut state = LinkState::new();
+    let mut changed = false;
+    let mut grouped: Vec<Vec<String>> = vec![];
+    let mut position = None;
+    let mut result = vec![];
+    for arg in argument::parse(kind, arguments) {
+        let tokens = arg.tokens(arguments);
+        let is_member = members.iter().zip(options.iter()).any(|(m, o)| {
+            (tokens.len() == 1 && tokens[0] == *m)
+                || o.as_ref().is_some_and(|(o, v)| arg.matches(o, v))
+        });
+        if is_member && !state.group {
+            if !grouped.iter().any(|g| g == tokens) {
+                grouped.push(tokens.to_vec());
+            }
+            position = Some(result.len());
+            continue;
+        }
+        if let Some(path) = tokens[0].strip_prefix("@") {
+            if let Some(res) = response_map.get_mut(path) {
+                res.changed |=
+                    group_link_arguments(members, kind, &mut res.values, &mut HashMap::new());
+            }
+        } else {
+            state.apply_argument(kind, &arg, tokens);
+        }
+        result.extend(tokens.iter().cloned());
+    }
+    if let Some(position) = position {
+        let mut group = vec![link_spelling(kind, "--start-group")];
+        group.extend(grouped.into_iter().flatten());
+        group.push(link_spelling(kind, "--end-group"));
+        result.splice(position..position, group);
+        changed = true;
+    }
+    *arguments = result;
+    changed
+}
+
+fn link_group_feature(key: String, kind: ToolKind, arg: &mut Configuration) -> Result<()> {
+    let members: Vec<&str> = key.split(',').filter(|m| !m.is_empty()).collect();
+    group_link_arguments(&members, kind, &mut arg.arguments, &mut arg.response_map);
+    Ok(())
+}
+
+fn remove_argument(
+    value: String,
+    before: Option<String>,
+    after: Option<String>,
+    kind: ToolKind,
+    args: &mut Vec<String>,
+    response_map: &mut HashMap<String, ResponseFile>,
+) -> Vec<String> {
+    remove_units(&value, &before, &after, kind, args, response_map)
+        .into_iter()
+        .flatten()
+        .collect()
+}
+
+// 按照逻辑参数处理, 带独立参数值的选项如 "-L /opt" 作为一个整体移动
+fn remove_units(
+    value: &str,
+    before: &Option<String>,
+    after: &Option<String>,
+    kind: ToolKind,
+    args: &mut Vec<String>,
+    response_map: &mut HashMap<String, ResponseFile>,
+) -> Vec<Vec<String>> {
+    let option = argument::parse_option(kind, value);
+    let mut units: Vec<(Argument, Vec<String>)> = argument::parse(kind, args)
+        .into_iter()
+        .map(|arg| {
+            let tokens = arg.tokens(args).to_vec();
+            (arg, tokens)
+        })
+        .collect();
+    let is_match = |(arg, tokens): &(Argument, Vec<String>)| {
+        if let Some((name, ref v)) = option {
+            if arg.matches(name, v) {
+                return true;
+            }
+        }
+        // 参数值不参与字符串匹配, 避免 -o 等选项的值被当作参数
+        tokens.len() == 1 && tokens[0].ends_with(value)
+    };
+
+    let mut result: Vec<Vec<String>> = vec![];
+    let mut i = 0;
+    while i < units.len() {
+        if let Some(path) = units[i].1[0].strip_prefix("@") {
+            if let Some(res) = response_map.get_mut(path) {
+                let elements = remove_units(
+                    value,
+                    before,
+                    after,
+                    kind,
+                    &mut res.values,
+                    &mut HashMap::new(),
+                );
+                res.changed |= !elements.is_empty();
+
+                for item in elements {
+                    if !result.contains(&item) {
+                        result.push(item);
+                    }
+                }
+            }
+        } else if is_match(&units[i]) {
+            // 通常用于移动静态库/动态库在开头或末尾,因此这里仅匹配结尾字符串
+            let remove = if let Some(ref before) = before {
+                i > 1 && units[i - 1].1.last().unwrap().ends_with(before)
+            } else if let Some(ref after) = after {
+                i < units.len() - 1 && units[i + 1].1[0].ends_with(after)
+            } else {
+                true
+            };
+            if remove {
+                let (_, lib) = units.remove(i);
+                if !result.contains(&lib) {
+                    result.push(lib);
+                }
+                continue;
+            }
+        }
+        i += 1;
+    }
+    *args = units.into_iter().flat_map(|(_, tokens)| tokens).collect();
+    result
+}
+
+fn move_to_back_for_before_feature(
+    value: String,
+    before: Option<String>,
+    arg: &mut Configuration,
+) -> Result<()> {
+    // 将匹配的指定参数移动到末尾
+    let mut result = remove_argument(
+        value.clone(),
+        before,
+        None,
+        arg.tool_kind(),
+        &mut arg.arguments,
+        &mut arg.response_map,
+    );
+    arg.arguments.append(&mut result);
+    Ok(())
+}
+
+fn move_to_back_for_after_feature(
+    value: String,
+    after: Option<String>,
+    arg: &mut Configuration,
+) -> Result<()> {
+    let mut result = remove_argument(
+        value.clone(),
+        None,
+        after,
+        arg.tool_kind(),
+        &mut arg.arguments,
+        &mut arg.response_map,
+    );
+    arg.arguments.append(&mut result);
+    Ok(())
+}
+
+fn move_to_front_for_before_feature(
+    value: String,
+    before: Option<String>,
+    arg: &mut Configuration,
+) -> Result<()> {
+    let result = remove_argument(
+        value.clone(),
+        before,
+        None,
+        arg.tool_kind(),
+        &mut arg.arguments,
+        &mut arg.response_map,
+    );
+    arg.arguments.splice(0..0, result);
+    Ok(())
+}
+
+fn move_to_front_for_after_feature(
+    value: String,
+    after: Option<String>,
+    arg: &mut Configuration,
+) -> Result<()> {
+    let result = remove_argument(
+        value.clone(),
+        None,
+        after,
+        arg.tool_kind(),
+        &mut arg.arguments,
+        &mut arg.response_map,
+    );
+    arg.arguments.splice(0..0, result);
+    Ok(())
+}
+
+fn replace_argument_feature(
+    key: String,
+    value: Option<String>,
+    arg: &mut Configuration,
+) -> Result<()> {
+    if let Some(value) = value {
+        // 可以识别的选项按照逻辑参数替换, 如 -L/opt 同时替换 "-L /opt" 的写法
+        let kind = arg.tool_kind();
+        if let Some((option, old)) = argument::parse_option(kind, &key) {
+            let f = |args: &mut Vec<String>| {
+                rewrite_arguments(kind, args, |a| {
+                    a.matches(option, &old).then(|| vec![value.clone()])
+                })
+            };
+            f(&mut arg.arguments);
+            for (_, v) in arg.response_map.iter_mut() {
+                v.changed |= f(&mut v.values);
+            }
+            return Ok(());
+        }
+        for arg in arg.arguments.iter_mut() {
+            if arg == &key {
+                *arg = value.clone();
+            }
+        }
+        for (_, v) in arg.response_map.iter_mut() {
+            v.replace_value(&key, &value)
+        }
+    }
+    Ok(())
+}
+
+fn remove_argument_feature(key: String, arg: &mut Configuration) -> Result<()> {
+    let kind = arg.tool_kind();
+    if let Some((option, value)) = argument::parse_option(kind, &key) {
+        let f = |args: &mut Vec<String>| {
+            rewrite_arguments(kind, args, |a| a.matches(option, &value).then(Vec::new))
+        };
+        f(&mut arg.arguments);
+        for (_, v) in arg.response_map.iter_mut() {
+            v.changed |= f(&mut v.values);
+        }
+        return Ok(());
+    }
+    arg.arguments.retain(|item| item != &key);
+    for (_, v) in arg.response_map.iter_mut() {
+        v.remove_value(&key)
+    }
+    Ok(())
+}
+
+// 使用 f 返回的参数替换对应的逻辑参数, 返回是否有修改
+fn rewrite_arguments(
+    kind: ToolKind,
+    args: &mut Vec<String>,
+    mut f: impl FnMut(&Argument) -> Option<Vec<String>>,
+) -> bool {
+    let mut changed = false;
+    let mut result = vec![];
+    for arg in argument::parse(kind, args) {
+        match f(&arg) {
+            Some(replacement) => {
+                result.extend(replacement);
+                changed = true;
+            }
+            None => result.extend(arg.tokens(args).iter().cloned()),
+        }
+    }
+    *args = result;
+    changed
+}
+
+pub fn reproducible_feature(config: &mut Configuration) {
+    if config.prefix_maps.is_empty() {
+        return;
+    }
+    // 已经设置的时间戳保持不变
+    if env::var_os("SOURCE_DATE_EPOCH").is_none() {
+        config
+            .environment
+            .push(("SOURCE_DATE_EPOCH".to_string(), "0".to_string()));
+    }
+    config
+        .environment
+        .push(("ZERO_AR_DATE".to_string(), "1".to_string()));
+
+    if !tool::is_compiler_driver(&config.command) {
+        return;
+    }
+    // -ffile-prefix-map 需要 gcc 8/clang 10 以上, 旧版本编译器只能映射调试信息中的路径
+    let option = if tool::supports_option(&config.command, "-ffile-prefix-map=/=/") {
+        "-ffile-prefix-map"
+    } else if tool::supports_option(&config.command, "-fdebug-prefix-map=/=/") {
+        "-fdebug-prefix-map"
+    } else {
+        warn!("{} does not support prefix map options", config.command);
+        return;
+    };
+    for (from, to) in config.prefix_maps.iter() {
+        config.arguments.push(format!("{}={}={}", option, from, to));
+    }
+}
+
+fn have_bool_environment_variable(key: &str) -> bool {
+    if let Ok(value) = env::var(key) {
+        let v = value.to_lowercase();
+        return v == "1" || v == "true" || v == "yes" || v == "on";
+    }
+    false
+}
+
+fn get_string_environment_variable(key: &str) -> String {
+    env::var(key).unwrap_or("".to_string())
+}
+
+// key 不包括 -clw- 前缀, 使用内置规则解析
+pub fn parse_arguments(config: &mut Configuration, key: &str) -> CommandType {
+    rule::builtin().parse(config, key)
+}
+
+// 使用内置规则对 argv 应用 -clw- 规则, 参见 Registry::rewrite
+pub fn rewrite<S: AsRef<str>>(argv: &[String], rules: &[S]) -> Result<Vec<String>> {
+    rule::builtin().rewrite(argv, rules)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_move_to_back_feature() {
+        let vec1: Vec<String> = vec![
+            "a0".to_owned(),
+            "prefix-a1".to_owned(),
+            "prefix-a2".to_owned(),
+            "a3".to_owned(),
+            "a1".to_owned(),
+            "a4".to_owned(),
+            "a5".to_owned(),
+        ];
+        let mut config = Configuration::new();
+        config.arguments = vec1.clone();
+        move_to_back_for_after_feature("a1".to_owned(), None, &mut config).unwrap();
+        assert_eq!(
+            config.arguments,
+            vec![
+                "a0".to_owned(),
+                "prefix-a2".to_owned(),
+                "a3".to_owned(),
+                "a4".to_owned(),
+                "a5".to_owned(),
+                "prefix-a1".to_owned(),
+                "a1".to_owned(),
+            ]
+        );
+
+        config.arguments = vec1.clone();
+        move_to_back_for_after_feature("a1".to_owned(), Some("a2".to_owned()), &mut config)
+            .unwrap();
+        assert_eq!(
+            config.arguments,
+            vec![
+                "a0".to_owned(),
+                "prefix-a2".to_owned(),
+                "a3".to_owned(),
+                "a1".to_owned(),
+                "a4".to_owned(),
+                "a5".to_owned(),
+                "prefix-a1".to_owned(),
+            ]
+        );
+
+        config.arguments = vec1.clone();
+        move_to_back_for_after_feature("a5".to_owned(), Some("after".to_owned()), &mut config)
+            .unwrap();
+        assert_eq!(config.arguments, vec1);
+
+        config.arguments = vec1.clone();
+        move_to_back_for_before_feature("a1".to_owned(), Some("none".to_owned()), &mut config)
+            .unwrap();
+        assert_eq!(config.arguments, vec1);
+
+        move_to_back_for_before_feature("a1".to_owned(), Some("a3".to_owned()), &mut config)
+            .unwrap();
+        assert_eq!(
+            config.arguments,
+            vec![
+                "a0".to_owned(),
+                "prefix-a1".to_owned(),
+                "prefix-a2".to_owned(),
+                "a3".to_owned(),
+                "a4".to_owned(),
+                "a5".to_owned(),
+                "a1".to_owned(),
+            ]
+        );
+        config.arguments = vec1.clone();
+        move_to_back_for_before_feature("a0".to_owned(), Some("before".to_owned()), &mut config)
+            .unwrap();
+        assert_eq!(config.arguments, vec1);
+    }
+
+    #[test]
+    fn test_move_to_before_feature() {
+        let vec1: Vec<String> = vec![
+            "a0".to_owned(),
+            "prefix-a1".to_owned(),
+            "prefix-a2".to_owned(),
+            "a3".to_owned(),
+            "a1".to_owned(),
+            "a4".to_owned(),
+            "a5".to_owned(),
+        ];
+        let mut config = Configuration::new();
+        config.arguments = vec1.clone();
+        move_to_front_for_after_feature("a1".to_owned(), None, &mut config).unwrap();
+        assert_eq!(
+            config.arguments,
+            vec![
+                "prefix-a1".to_owned(),
+                "a1".to_owned(),
+                "a0".to_owned(),
+                "prefix-a2".to_owned(),
+                "a3".to_owned(),
+                "a4".to_owned(),
+                "a5".to_owned(),
+            ]
+        );
+
+        config.arguments = vec1.clone();
+        move_to_front_for_after_feature("a1".to_owned(), Some("a2".to_owned()), &mut config)
+            .unwrap();
+        assert_eq!(
+            config.arguments,
+            vec![
+                "prefix-a1".to_owned(),
+                "a0".to_owned(),
+                "prefix-a2".to_owned(),
+                "a3".to_owned(),
+                "a1".to_owned(),
+                "a4".to_owned(),
+                "a5".to_owned(),
+            ]
+        );
+
+        config.arguments = vec1.clone();
+        move_to_front_for_after_feature("a5".to_owned(), Some("after".to_owned()), &mut config)
+            .unwrap();
+        assert_eq!(config.arguments, vec1);
+
+        config.arguments = vec1.clone();
+        move_to_front_for_before_feature("a1".to_owned(), Some("none".to_owned()), &mut config)
+            .unwrap();
+        assert_eq!(config.arguments, vec1);
+
+        move_to_front_for_before_feature("a1".to_owned(), Some("a3".to_owned()), &mut config)
+            .unwrap();
+        assert_eq!(
+            config.arguments,
+            vec![
+                "a1".to_owned(),
+                "a0".to_owned(),
+                "prefix-a1".to_owned(),
+                "prefix-a2".to_owned(),
+                "a3".to_owned(),
+                "a4".to_owned(),
+                "a5".to_owned(),
+            ]
+        );
+        config.arguments = vec1.clone();
+        move_to_front_for_before_feature("a0".to_owned(), Some("before".to_owned()), &mut config)
+            .unwrap();
+        assert_eq!(config.arguments, vec1);
+    }
+    #[test]
+    fn test_tool_aware_arguments() {
+        let mut config = Configuration::new();
+        config.command = "gcc".to_owned();
+        config.arguments = vec![
+            "-o".to_owned(),
+            "x-lm".to_owned(),
+            "-L".to_owned(),
+            "/opt".to_owned(),
+            "-L/opt".to_owned(),
+            "-lm".to_owned(),
+            "a.c".to_owned(),
+        ];
+        remove_argument_feature("-L/opt".to_owned(), &mut config).unwrap();
+        assert_eq!(config.arguments, vec!["-o", "x-lm", "-lm", "a.c"]);
+
+        move_to_back_for_before_feature("-lm".to_owned(), None, &mut config).unwrap();
+        assert_eq!(config.arguments, vec!["-o", "x-lm", "a.c", "-lm"]);
+
+        replace_argument_feature("-l m".to_owned(), Some("-lm2".to_owned()), &mut config).unwrap();
+        assert_eq!(config.arguments, vec!["-o", "x-lm", "a.c", "-lm2"]);
+    }
+    #[test]
+    fn test_link_feature() {
+        let mut config = Configuration::new();
+        config.arguments = vec![
+            "a.o".to_owned(),
+            "-Wl,--push-state,-Bstatic,-lfoo".to_owned(),
+            "-lc".to_owned(),
+            "-Wl,--pop-state".to_owned(),
+            "-lc".to_owned(),
+            "-Wl,-Bstatic,-lc,-Bdynamic".to_owned(),
+            "-Xlinker".to_owned(),
+            "-Bstatic".to_owned(),
+            "-lc".to_owned(),
+        ];
+        static_link_feature("-lc".to_owned(), ToolKind::Compiler, &mut config).unwrap();
+        assert_eq!(
+            config.arguments,
+            vec![
+                "a.o",
+                "-Wl,--push-state,-Bstatic,-lfoo",
+                "-lc",
+                "-Wl,--pop-state",
+                "-Wl,-Bstatic",
+                "-lc",
+                "-Wl,-lc,-Bdynamic",
+                "-Xlinker",
+                "-Bstatic",
+                "-lc",
+            ]
+        );
+
+        dynamic_link_feature("-lc".to_owned(), ToolKind::Compiler, &mut config).unwrap();
+        assert_eq!(
+            config.arguments,
+            vec![
+                "a.o",
+                "-Wl,--push-state,-Bstatic,-lfoo",
+                "-Wl,-Bdynamic",
+                "-lc",
+                "-Wl,--pop-state",
+                "-lc",
+                "-Wl,-lc",
+                "-lc",
+                "-Wl,-Bstatic",
+            ]
+        );
+
+        config.arguments = vec![
+            "-Bshareable".to_owned(),
+            "--as-needed".to_owned(),
+            "-l".to_owned(),
+            "c".to_owned(),
+        ];
+        static_link_feature("-lc".to_owned(), ToolKind::Linker, &mut config).unwrap();
+        assert_eq!(
+            config.arguments,
+            vec![
+                "-Bshareable",
+                "--as-needed",
+                "-Bstatic",
+                "-l",
+                "c",
+                "-Bdynamic"
+            ]
+        );
+    }
+    #[test]
+    fn test_whole_archive_and_link_group() {
+        let mut config = Configuration::new();
+        config.arguments = vec![
+            "a.o".to_owned(),
+            "libreg.a".to_owned(),
+            "-lx".to_owned(),
+            "-Wl,--whole-archive".to_owned(),
+            "libreg.a".to_owned(),
+            "-Wl,--no-whole-archive".to_owned(),
+            "-ly".to_owned(),
+            "-lz".to_owned(),
+        ];
+        whole_archive_feature("libreg.a".to_owned(), ToolKind::Compiler, &mut config).unwrap();
+        assert_eq!(
+            config.arguments,
+            vec![
+                "a.o",
+                "-Wl,--whole-archive",
+                "libreg.a",
+                "-Wl,--no-whole-archive",
+                "-lx",
+                "-Wl,--whole-archive",
+                "libreg.a",
+                "-Wl,--no-whole-archive",
+                "-ly",
+                "-lz",
+            ]
+        );
+
+        config.arguments = vec![
+            "a.o".to_owned(),
+            "-lx".to_owned(),
+            "-lm".to_owned(),
+            "-l".to_owned(),
+            "y".to_owned(),
+            "-lx".to_owned(),
+            "-lc".to_owned(),
+        ];
+        link_group_feature("-lx,-ly".to_owned(), ToolKind::Linker, &mut config).unwrap();
+        assert_eq!(
+            config.arguments,
+            vec![
+                "a.o",
+                "-lm",
+                "--start-group",
+                "-lx",
+                "-l",
+                "y",
+                "--end-group",
+                "-lc"
+            ]
+        );
+    }
+    #[test]
+    fn test_all_static_feature() {
+        let mut config = Configuration::new();
+        config.arguments = vec![
+            "a.o".to_owned(),
+            "-lfoo".to_owned(),
+            "-Wl,-Bstatic,-lbar,-Bdynamic".to_owned(),
+            "-l".to_owned(),
+            "c".to_owned(),
+            "-ldl".to_owned(),
+        ];
+        all_static_feature("-lc,dl".to_owned(), ToolKind::Compiler, &mut config).unwrap();
+        assert_eq!(
+            config.arguments,
+            vec![
+                "a.o",
+                "-Wl,-Bstatic",
+                "-lfoo",
+                "-Wl,-lbar,-Bdynamic",
+                "-l",
+                "c",
+                "-ldl",
+            ]
+        );
+
+        all_dynamic_feature(String::new(), ToolKind::Compiler, &mut config).unwrap();
+        assert_eq!(
+            config.arguments,
+            vec!["a.o", "-lfoo", "-Wl,-lbar", "-l", "c", "-ldl"]
+        );
+
+        // 相邻的库共用一个区域
+        config.arguments = ["a.o", "-lfoo", "-lbar", "-lc", "-lbaz"]
+            .map(String::from)
+            .to_vec();
+        all_static_feature("c".to_owned(), ToolKind::Compiler, &mut config).unwrap();
+        assert_eq!(
+            config.arguments,
+            vec![
+                "a.o",
+                "-Wl,-Bstatic",
+                "-lfoo",
+                "-lbar",
+                "-Wl,-Bdynamic",
+                "-lc",
+                "-Wl,-Bstatic",
+                "-lbaz",
+                "-Wl,-Bdynamic",
+            ]
+        );
+    }
+    #[test]
+    fn test_rewrite() {
+        let argv: Vec<String> = ["gcc", "a.o", "-lm", "-L", "/opt", "-o", "a"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        assert_eq!(
+            rewrite(&argv, &["-clw-remove=-L/opt", "move-back=-lm"]).unwrap(),
+            vec!["gcc", "a.o", "-o", "a", "-lm"]
+        );
+        assert!(rewrite(&argv, &["-clw-unknown"]).is_err());
+        assert!(rewrite(&[], &["remove=-lm"]).is_err());
+    }
+}
@@ -0,0 +1,609 @@
+use anyhow::{bail, Result};
+use simplelog::{debug, info, warn};
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BinaryHeap, HashMap};
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use crate::argument::{self, ToolKind};
+use crate::elf::SymbolTable;
+use crate::{link_spelling, rewrite_arguments, Configuration, LinkState, ResponseFile};
+
+// 链接库的搜索路径, 顺序为 -L 参数, sysroot 和工具链默认路径
+pub fn library_search_dirs(config: &Configuration) -> Vec<PathBuf> {
+    let kind = link_tool_kind(config);
+    let arguments = config.expanded_arguments();
+    let parsed = argument::parse(kind, &arguments);
+    let sysroot = parsed
+        .iter()
+        .find(|arg| arg.is("--sysroot"))
+        .and_then(|arg| arg.value.clone());
+
+    let mut dirs = vec![];
+    let mut push = |dir: &str| {
+        // ld 中以 = 开头的路径相对于 sysroot
+        let path = match (dir.strip_prefix('='), sysroot.as_ref()) {
+            (Some(rest), Some(sysroot)) => Path::new(sysroot).join(rest.trim_start_matches('/')),
+            (Some(rest), None) => PathBuf::from(rest),
+            _ => PathBuf::from(dir),
+        };
+        let path = if path.is_relative() && !config.work_dir.is_empty() {
+            Path::new(&config.work_dir).join(path)
+        } else {
+            path
+        };
+        if !dir.is_empty() && !dirs.contains(&path) {
+            dirs.push(path);
+        }
+    };
+
+    for arg in parsed.iter().filter(|arg| arg.is("-L")) {
+        push(arg.value.as_deref().unwrap_or(""));
+    }
+    if let Some(ref sysroot) = sysroot {
+        for dir in ["lib", "usr/lib", "usr/local/lib"] {
+            push(&Path::new(sysroot).join(dir).to_string_lossy());
+        }
+    }
+    for dir in toolchain_search_dirs(config, kind, &arguments) {
+        push(&dir);
+    }
+    dirs
+}
+
+fn link_tool_kind(config: &Configuration) -> ToolKind {
+    match config.tool_kind() {
+        ToolKind::Unknown => ToolKind::Compiler,
+        kind => kind,
+    }
+}
+
+fn toolchain_search_dirs(
+    config: &Configuration,
+    kind: ToolKind,
+    arguments: &[String],
+) -> Vec<String> {
+    let mut command = Command::new(&config.command);
+    if kind == ToolKind::Compiler {
+        // 目标架构相关的参数会影响 multilib 路径
+        command.args(arguments.iter().filter(|arg| {
+            arg.starts_with("-m")
+                || arg.starts_with("--sysroot")
+                || arg.starts_with("--target")
+                || arg.starts_with("-B")
+        }));
+        command.arg("-print-search-dirs");
+    } else {
+        command.arg("--verbose");
+    }
+    if !config.work_dir.is_empty() {
+        command.current_dir(&config.work_dir);
+    }
+    let output = match command.stdin(Stdio::null()).stderr(Stdio::null()).output() {
+        Ok(output) if output.status.success() => {
+            String::from_utf8_lossy(&output.stdout).into_owned()
+        }
+        _ => return vec![],
+    };
+
+    let mut dirs = vec![];
+    for line in output.lines() {
+        if let Some(list) = line.strip_prefix("libraries: ") {
+            // gcc 输出 libraries: =/usr/lib/gcc/x86_64-linux-gnu/12/:/usr/lib/...
+            let list = list.trim_start_matches('=');
+            dirs.extend(env::split_paths(list).map(|p| p.to_string_lossy().into_owned()));
+        } else if kind == ToolKind::Linker {
+            // ld --verbose 的链接脚本中包含 SEARCH_DIR("=/usr/lib");
+            for item in line.split(';') {
+                if let Some(dir) = item
+                    .trim()
+                    .strip_prefix("SEARCH_DIR(\"")
+                    .and_then(|d| d.strip_suffix("\")"))
+                {
+                    dirs.push(dir.to_string());
+                }
+            }
+        }
+    }
+    dirs
+}
+
+fn find_library(dirs: &[PathBuf], names: &[String]) -> Option<PathBuf> {
+    dirs.iter()
+        .flat_map(|dir| names.iter().map(move |name| dir.join(name)))
+        .find(|path| path.is_file())
+}
+
+pub fn resolve_static_feature(key: String, config: &mut Configuration) -> Result<()> {
+    let name = key.strip_prefix("-l").unwrap_or(&key).trim().to_string();
+    if name.is_empty() {
+        return Ok(());
+    }
+    let kind = link_tool_kind(config);
+    let is_target = |arg: &argument::Argument| arg.matches("-l", &name);
+    let used = argument::parse(kind, &config.expanded_arguments())
+        .iter()
+        .any(is_target);
+    if !used {
+        return Ok(());
+    }
+
+    let dirs = library_search_dirs(config);
+    // -l:libfoo.a 形式直接指定了文件名
+    let (archives, shared) = match name.strip_prefix(':') {
+        Some(file) => (vec![file.to_string()], vec![]),
+        None => (
+            vec![format!("lib{}.a", name)],
+            vec![
+                format!("lib{}.so", name),
+                format!("lib{}.dylib", name),
+                format!("lib{}.dll.a", name),
+            ],
+        ),
+    };
+    let archive = match find_library(&dirs, &archives) {
+        Some(archive) => archive,
+        None => {
+            let searched = dirs
+                .iter()
+                .map(|d| d.to_string_lossy().into_owned())
+                .collect::<Vec<String>>()
+                .join(", ");
+            if let Some(library) = find_library(&dirs, &shared) {
+                bail!(
+                    "cannot link -l{} statically, only the shared library {} was found, searched directories: {}",
+                    name,
+                    library.to_string_lossy(),
+                    searched
+                );
+            }
+            warn!("-l{} was not found in: {}", name, searched);
+            return Ok(());
+        }
+    };
+    let archive = std::fs::canonicalize(&archive)
+        .unwrap_or(archive)
+        .to_string_lossy()
+        .into_owned();
+    debug!("resolve -l{} to {}", name, archive);
+
+    let f = |args: &mut Vec<String>| {
+        rewrite_arguments(kind, args, |arg| {
+            is_target(arg).then(|| vec![archive.clone()])
+        })
+    };
+    f(&mut config.arguments);
+    for (_, v) in config.response_map.iter_mut() {
+        v.changed |= f(&mut v.values);
+    }
+    Ok(())
+}
+
+// 参与排序的归档文件
+struct ArchiveUnit {
+    tokens: Vec<String>,
+    path: PathBuf,
+    dynamic: bool,
+    symbols: SymbolTable,
+}
+
+enum Item {
+    Fixed(Vec<String>),
+    // 归档文件所在的位置和当时的链接状态
+    Slot(bool),
+}
+
+fn resolve_archive(
+    arg: &argument::Argument,
+    tokens: &[String],
+    state: &LinkState,
+    dirs: &[PathBuf],
+    work_dir: &str,
+) -> Option<PathBuf> {
+    if arg.is("-l") {
+        let name = arg.value.as_deref()?;
+        if let Some(file) = name.strip_prefix(':') {
+            return find_library(dirs, &[file.to_string()]);
+        }
+        // 动态链接时优先使用动态库, 动态库的顺序不影响符号解析
+        let shared = [format!("lib{}.so", name), format!("lib{}.dylib", name)];
+        if state.dynamic && find_library(dirs, &shared).is_some() {
+            return None;
+        }
+        return find_library(dirs, &[format!("lib{}.a", name)]);
+    }
+    if arg.option.is_some() || tokens.len() != 1 || !tokens[0].ends_with(".a") {
+        return None;
+    }
+    let path = Path::new(&tokens[0]);
+    let path = if path.is_relative() && !work_dir.is_empty() {
+        Path::new(work_dir).join(path)
+    } else {
+        path.to_path_buf()
+    };
+    path.is_file().then_some(path)
+}
+
+// 命令行中的目标文件, 链接器总是加载它们
+fn read_object(arg: &argument::Argument, tokens: &[String], work_dir: &str) -> Option<SymbolTable> {
+    if arg.option.is_some() || tokens.len() != 1 || !tokens[0].ends_with(".o") {
+        return None;
+    }
+    let path = Path::new(work_dir).join(&tokens[0]);
+    let data = fs::read(&path).ok()?;
+    SymbolTable::read(&data)
+        .map_err(|e| debug!("skip {}: {}", path.to_string_lossy(), e))
+        .ok()
+}
+
+// 从目标文件的未定义符号开始, 计算会被拉取的归档. 没有可以读取的目标文件时认为全部归档都会被拉取
+fn reached_archives(units: &[ArchiveUnit], objects: &SymbolTable) -> Vec<bool> {
+    if objects.defined.is_empty() && objects.undefined.is_empty() {
+        return vec![true; units.len()];
+    }
+    let mut reached = vec![false; units.len()];
+    let mut pending: Vec<&String> = objects.undefined.iter().collect();
+    while let Some(symbol) = pending.pop() {
+        for (i, unit) in units.iter().enumerate() {
+            if !reached[i] && unit.symbols.defined.contains(symbol) {
+                reached[i] = true;
+                pending.extend(unit.symbols.undefined.iter());
+            }
+        }
+    }
+    reached
+}
+
+// Tarjan 算法计算强连通分量, 分量中的归档相互依赖
+fn strongly_connected(edges: &[Vec<usize>]) -> Vec<usize> {
+    struct Tarjan<'a> {
+        edges: &'a [Vec<usize>],
+        index: Vec<Option<usize>>,
+        low: Vec<usize>,
+        stack: Vec<usize>,
+        on_stack: Vec<bool>,
+        component: Vec<usize>,
+        next: usize,
+        count: usize,
+    }
+
+    impl Tarjan<'_> {
+        fn visit(&mut self, v: usize) {
+            self.index[v] = Some(self.next);
+            self.low[v] = self.next;
+            self.next += 1;
+            self.stack.push(v);
+            self.on_stack[v] = true;
+            for &w in self.edges[v].iter() {
+                match self.index[w] {
+                    None => {
+                        self.visit(w);
+                        self.low[v] = self.low[v].min(self.low[w]);
+                    }
+                    Some(index) if self.on_stack[w] => self.low[v] = self.low[v].min(index),
+                    _ => {}
+                }
+            }
+            if Some(self.low[v]) == self.index[v] {
+                while let Some(w) = self.stack.pop() {
+                    self.on_stack[w] = false;
+                    self.component[w] = self.count;
+                    if w == v {
+                        break;
+                    }
+                }
+                self.count += 1;
+            }
+        }
+    }
+
+    let n = edges.len();
+    let mut tarjan = Tarjan {
+        edges,
+        index: vec![None; n],
+        low: vec![0; n],
+        stack: vec![],
+        on_stack: vec![false; n],
+        component: vec![0; n],
+        next: 0,
+        count: 0,
+    };
+    for v in 0..n {
+        if tarjan.index[v].is_none() {
+            tarjan.visit(v);
+        }
+    }
+    tarjan.component
+}
+
+// 稳定的拓扑排序, 没有依赖约束时保持原来的顺序, 返回分组后的归档顺序
+fn sort_archives(edges: &[Vec<usize>]) -> Vec<Vec<usize>> {
+    let component = strongly_connected(edges);
+    let count = component.iter().map(|c| c + 1).max().unwrap_or(0);
+    let mut members: Vec<Vec<usize>> = vec![vec![]; count];
+    for (v, &c) in component.iter().enumerate() {
+        members[c].push(v);
+    }
+    let mut successors: Vec<Vec<usize>> = vec![vec![]; count];
+    let mut degree = vec![0; count];
+    for (v, targets) in edges.iter().enumerate() {
+        for &w in targets {
+            let (from, to) = (component[v], component[w]);
+            if from != to && !successors[from].contains(&to) {
+                successors[from].push(to);
+                degree[to] += 1;
+            }
+        }
+    }
+    let mut heap: BinaryHeap<Reverse<(usize, usize)>> = (0..count)
+        .filter(|&c| degree[c] == 0)
+        .map(|c| Reverse((members[c][0], c)))
+        .collect();
+    let mut order = vec![];
+    while let Some(Reverse((_, c))) = heap.pop() {
+        for &next in successors[c].iter() {
+            degree[next] -= 1;
+            if degree[next] == 0 {
+                heap.push(Reverse((members[next][0], next)));
+            }
+        }
+        order.push(members[c].clone());
+    }
+    order
+}
+
+fn reorder_archives(
+    kind: ToolKind,
+    dirs: &[PathBuf],
+    work_dir: &str,
+    arguments: &mut Vec<String>,
+    response_map: &mut HashMap<String, ResponseFile>,
+) -> bool {
+    let mut state = LinkState::new();
+    let mut items = vec![];
+    let mut units: Vec<ArchiveUnit> = vec![];
+    let mut objects = SymbolTable::default();
+    for arg in argument::parse(kind, arguments) {
+        let tokens = arg.tokens(arguments);
+        if let Some(path) = tokens[0].strip_prefix("@") {
+            if let Some(res) = response_map.get_mut(path) {
+                res.changed |=
+                    reorder_archives(kind, dirs, work_dir, &mut res.values, &mut HashMap::new());
+            }
+        } else if !state.group && !state.whole_archive {
+            // 重复出现的归档保持原来的位置
+            let archive = resolve_archive(&arg, tokens, &state, dirs, work_dir)
+                .filter(|path| !units.iter().any(|u| &u.path == path));
+            if let Some(path) = archive {
+                match fs::read(&path)
+                    .map_err(anyhow::Error::from)
+                    .and_then(|data| SymbolTable::read(&data))
+                {
+                    Ok(symbols) => {
+                        items.push(Item::Slot(state.dynamic));
+                        units.push(ArchiveUnit {
+                            tokens: tokens.to_vec(),
+                            path,
+                            dynamic: state.dynamic,
+                            symbols,
+                        });
+                        continue;
+                    }
+                    Err(e) => debug!("skip {}: {}", path.to_string_lossy(), e),
+                }
+            }
+        }
+        if let Some(symbols) = read_object(&arg, tokens, work_dir) {
+            objects.defined.extend(symbols.defined);
+            objects.undefined.extend(symbols.undefined);
+        }
+        state.apply_argument(kind, &arg, tokens);
+        items.push(Item::Fixed(tokens.to_vec()));
+    }
+
+    // a 引用了 b 定义的符号时 a 必须位于 b 之前, 只考虑目标文件直接或者间接引用的归档
+    let reached = reached_archives(&units, &objects);
+    let mut edges: Vec<Vec<usize>> = vec![vec![]; units.len()];
+    let mut reasons: BTreeMap<(usize, usize), &str> = BTreeMap::new();
+    for (a, unit) in units.iter().enumerate().filter(|(a, _)| reached[*a]) {
+        for (b, other) in units.iter().enumerate() {
+            let symbol = unit
+                .symbols
+                .undefined
+                .iter()
+                .filter(|s| !objects.defined.contains(*s) && other.symbols.defined.contains(*s))
+                .min();
+            if let (true, Some(symbol)) = (a != b, symbol) {
+                edges[a].push(b);
+                reasons.insert((a, b), symbol);
+            }
+        }
+    }
+    let order = sort_archives(&edges);
+    let name = |i: usize| units[i].tokens.join(" ");
+    let mut changed = false;
+    for group in order.iter() {
+        if group.len() > 1 {
+            let (a, b) = (group[0], group[1]);
+            info!(
+                "fix-link-order: {} depend on each other (`{}`), wrapped in a link group",
+                group
+                    .iter()
+                    .map(|&i| name(i))
+                    .collect::<Vec<String>>()
+                    .join(", "),
+                reasons.get(&(a, b)).or(reasons.get(&(b, a))).unwrap_or(&"")
+            );
+            changed = true;
+        }
+    }
+    for (&(a, b), symbol) in reasons.iter() {
+        // 只报告被移动的归档
+        if a > b && !order.iter().any(|g| g.contains(&a) && g.contains(&b)) {
+            info!(
+                "fix-link-order: moved {} after {}, which references `{}`",
+                name(b),
+                name(a),
+                symbol
+            );
+            changed = true;
+        }
+    }
+    if !changed {
+        return false;
+    }
+
+    // 每个分组连续地放在它占用的第一个位置, 之后的位置留空, 其它参数不会进入 group
+    let mut groups = order.into_iter();
+    let mut skip = 0;
+    let mut result = vec![];
+    for item in items {
+        match item {
+            Item::Fixed(tokens) => result.extend(tokens),
+            Item::Slot(_) if skip > 0 => skip -= 1,
+            Item::Slot(dynamic) => {
+                let group = groups.next().unwrap();
+                skip = group.len() - 1;
+                if group.len() > 1 {
+                    result.push(link_spelling(kind, "--start-group"));
+                }
+                for &v in group.iter() {
+                    let unit = &units[v];
+                    if unit.dynamic == dynamic {
+                        result.extend(unit.tokens.iter().cloned());
+                    } else {
+                        // 移动到链接方式不同的位置时直接使用归档路径
+                        result.push(unit.path.to_string_lossy().into_owned());
+                    }
+                }
+                if group.len() > 1 {
+                    result.push(link_spelling(kind, "--end-group"));
+                }
+            }
+        }
+    }
+    *arguments = result;
+    true
+}
+
+pub fn fix_link_order_feature(_: String, kind: ToolKind, config: &mut Configuration) -> Result<()> {
+    let has_library = argument::parse(kind, &config.expanded_arguments())
+        .iter()
+        .any(|arg| arg.is("-l"));
+    // 只有 -l 参数需要查询搜索路径
+    let dirs = if has_library {
+        library_search_dirs(config)
+    } else {
+        vec![]
+    };
+    let work_dir = config.work_dir.clone();
+    reorder_archives(
+        kind,
+        &dirs,
+        &work_dir,
+        &mut config.arguments,
+        &mut config.response_map,
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_resolve_static_feature() {
+        let dir = env::temp_dir().join(format!("clw_resolve_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("libbar.a"), "").unwrap();
+        fs::write(dir.join("libfoo.so"), "").unwrap();
+        let lib_dir = dir.to_string_lossy().into_owned();
+
+        let mut config = Configuration::new();
+        // 不存在的编译器, 不会查询工具链的默认路径
+        config.command = "clw-test-missing-gcc".to_owned();
+        config.arguments = vec![
+            "-L".to_owned(),
+            lib_dir.clone(),
+            "-l".to_owned(),
+            "bar".to_owned(),
+            "-lfoo".to_owned(),
+        ];
+        resolve_static_feature("-lbar".to_owned(), &mut config).unwrap();
+        let archive = fs::canonicalize(dir.join("libbar.a")).unwrap();
+        assert_eq!(
+            config.arguments,
+            vec![
+                "-L".to_owned(),
+                lib_dir.clone(),
+                archive.to_string_lossy().into_owned(),
+                "-lfoo".to_owned(),
+            ]
+        );
+
+        let error = resolve_static_feature("foo".to_owned(), &mut config).unwrap_err();
+        assert!(error.to_string().contains(&lib_dir));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_fix_link_order_feature() {
+        use crate::elf::tests::{archive, object};
+
+        let dir = env::temp_dir().join(format!("clw_link_order_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let write = |name: &str, defined: &[&str], undefined: &[&str]| {
+            let data = archive(&[("m.o", object(defined, undefined))]);
+            fs::write(dir.join(name), data).unwrap();
+        };
+        write("libapp.a", &["app"], &["util", "ping"]);
+        write("libutil.a", &["util"], &[]);
+        write("libping.a", &["ping"], &["pong"]);
+        write("libpong.a", &["pong"], &["ping"]);
+        // 目标文件没有引用, 不会被拉取
+        write("libextra.a", &["extra"], &["app"]);
+        fs::write(dir.join("main.o"), object(&["main"], &["app"])).unwrap();
+        let lib_dir = dir.to_string_lossy().into_owned();
+
+        let mut config = Configuration::new();
+        config.command = "clw-test-missing-gcc".to_owned();
+        config.work_dir = lib_dir.clone();
+        config.arguments = vec![
+            "main.o".to_owned(),
+            format!("-L{}", lib_dir),
+            "-lutil".to_owned(),
+            "-lping".to_owned(),
+            "-lapp".to_owned(),
+            "-lm".to_owned(),
+            "-lpong".to_owned(),
+            "-lextra".to_owned(),
+        ];
+        fix_link_order_feature(String::new(), ToolKind::Compiler, &mut config).unwrap();
+        assert_eq!(
+            config.arguments,
+            vec![
+                "main.o".to_owned(),
+                format!("-L{}", lib_dir),
+                "-lapp".to_owned(),
+                "-lutil".to_owned(),
+                "-Wl,--start-group".to_owned(),
+                "-lping".to_owned(),
+                "-lpong".to_owned(),
+                "-Wl,--end-group".to_owned(),
+                "-lm".to_owned(),
+                "-lextra".to_owned(),
+            ]
+        );
+
+        // 已经处于 group 中的归档不再处理
+        let arguments = config.arguments.clone();
+        fix_link_order_feature(String::new(), ToolKind::Compiler, &mut config).unwrap();
+        assert_eq!(config.arguments, arguments);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
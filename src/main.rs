@@ -1,698 +1,24 @@
-use anyhow::Result;
+mod check;
+mod cli;
+mod install;
+
+use anyhow::{bail, Result};
+use cli_wrapper::diagnostic::{self, DiagnosticFilter};
+use cli_wrapper::hook::{self, Phase};
+use cli_wrapper::{atomic, cache, cmdline, depfile, output, report, rule, status, tool, verify};
+use cli_wrapper::{
+    parse_arguments, reproducible_feature, CommandType, Configuration, ResponseFile,
+};
 use simplelog::*;
-use std::collections::HashMap;
 use std::env;
 use std::fs::{self, File, OpenOptions};
+use std::io::Write;
 use std::path::Path;
 use std::process::{Command, Stdio};
+use std::thread;
+use std::time::Instant;
 
-struct ResponseFile {
-    original_path: String,
-    new_path: String,
-    values: Vec<String>,
-    changed: bool,
-}
-
-impl ResponseFile {
-    fn new(original_path: String, new_path: String) -> ResponseFile {
-        ResponseFile {
-            original_path: original_path.clone(),
-            new_path,
-            values: Self::read_response_file(&original_path).unwrap_or(vec![]),
-            changed: false,
-        }
-    }
-
-    fn remove_value(&mut self, value: &str) {
-        let old = self.values.len();
-        self.values.retain(|v| v != value);
-        self.changed |= old != self.values.len();
-    }
-
-    fn replace_value(&mut self, old: &str, new: &str) {
-        for arg in self.values.iter_mut() {
-            if arg == old {
-                *arg = new.to_string();
-                self.changed = true;
-            }
-        }
-    }
-
-    fn read_response_file(path: &str) -> Result<Vec<String>> {
-        let content = fs::read_to_string(path)?.replace("\r\n", "\n");
-        Ok(Self::parse_response_file(content))
-    }
-
-    fn write_response_file(&self) -> Result<()> {
-        let content: String = self
-            .values
-            .iter()
-            .map(Self::escape)
-            .collect::<Vec<String>>()
-            .join(" ");
-
-        fs::write(&self.new_path, content)?;
-        Ok(())
-    }
-
-    fn escape(arg: &String) -> String {
-        let mut result = String::new();
-        let mut needs_quotes = false;
-
-        for c in arg.chars() {
-            match c {
-                ' ' => {
-                    needs_quotes = true;
-                    result.push(c);
-                }
-                '\t' => {
-                    needs_quotes = true;
-                    result.push_str("\\t");
-                }
-                '"' => {
-                    needs_quotes = true;
-                    result.push_str("\\\"");
-                }
-                '\\' => {
-                    result.push_str("\\\\");
-                }
-                _ => {
-                    result.push(c);
-                }
-            }
-        }
-
-        if needs_quotes {
-            format!("\"{}\"", result)
-        } else {
-            result
-        }
-    }
-
-    fn unescape(arg: &str) -> String {
-        let mut result = String::new();
-        let mut chars = arg.chars();
-        while let Some(c) = chars.next() {
-            if c == '\\' {
-                if let Some(next_char) = chars.next() {
-                    match next_char {
-                        '"' => result.push('"'),
-                        '\\' => result.push('\\'),
-                        _ => {
-                            result.push('\\');
-                            result.push(next_char);
-                        }
-                    }
-                } else {
-                    result.push('\\');
-                }
-            } else {
-                result.push(c);
-            }
-        }
-        result
-    }
-
-    // Function to parse the content of the response file into arguments
-    fn parse_response_file(content: String) -> Vec<String> {
-        let mut args = Vec::new();
-        let mut current_arg = String::new();
-        let mut in_quotes = false;
-        let mut escape_next = false;
-
-        for c in content.chars() {
-            if escape_next {
-                current_arg.push(c);
-                escape_next = false;
-            } else if c == '\\' {
-                escape_next = true;
-            } else if c == '"' {
-                in_quotes = !in_quotes;
-            } else if c.is_whitespace() && !in_quotes {
-                if !current_arg.is_empty() {
-                    args.push(Self::unescape(&current_arg));
-                    current_arg.clear();
-                }
-            } else {
-                current_arg.push(c);
-            }
-        }
-
-        if !current_arg.is_empty() {
-            args.push(Self::unescape(&current_arg));
-        }
-
-        args
-    }
-}
-
-struct Configuration {
-    command: String,
-    work_dir: String,
-    just_print: bool,
-    before_print: bool,
-    redirect_stdout: String,
-    redirect_stderr: String,
-    arguments: Vec<String>,
-    response_map: HashMap<String, ResponseFile>,
-    log_file: String,
-}
-
-impl Configuration {
-    fn new() -> Configuration {
-        // 部分配置可以从环境变量读取默认值
-        Configuration {
-            command: get_string_environment_variable("CLW_OPT_COMMAND"),
-            work_dir: "".to_string(),
-            just_print: have_bool_environment_variable("CLW_OPT_JUST_PRINT"),
-            before_print: have_bool_environment_variable("CLW_OPT_BEFORE_PRINT"),
-            redirect_stdout: get_string_environment_variable("CLW_OPT_REDIRECT_STDOUT"),
-            redirect_stderr: get_string_environment_variable("CLW_OPT_REDIRECT_STDERR"),
-            arguments: vec![],
-            response_map: HashMap::new(),
-            log_file: get_string_environment_variable("CLW_LOG_FILE"),
-        }
-    }
-
-    fn replace_response_file(&mut self) -> Result<()> {
-        // 不支持嵌套 ResponseFile
-        for (_, v) in self.response_map.iter() {
-            if v.changed {
-                v.write_response_file()?;
-                let before = "@".to_string() + &v.original_path;
-                let after = "@".to_string() + &v.new_path;
-
-                for arg in self.arguments.iter_mut() {
-                    if arg == &before {
-                        *arg = after.clone();
-                    }
-                }
-            }
-        }
-        Ok(())
-    }
-}
-
-impl Drop for Configuration {
-    fn drop(&mut self) {
-        self.response_map
-            .values()
-            .map(|f| fs::remove_file(&f.new_path).unwrap_or(()))
-            .count();
-    }
-}
-
-fn change_link_feature(
-    key: String,
-    is_linker: Option<String>,
-    dynamic_link: bool,
-    mut is_dynamic: bool,
-    arguments: &mut Vec<String>,
-    response_map: &mut HashMap<String, ResponseFile>,
-) -> bool {
-    // 更改链接方式但不更改链接顺序, 因为有些情况下链接顺序很重要
-    let (static_key, dynamic_key) = if is_linker.is_some() {
-        ("-Bstatic".to_owned(), "-Bdynamic".to_owned())
-    } else {
-        ("-Wl,-Bstatic".to_owned(), "-Wl,-Bdynamic".to_owned())
-    };
-    let mut i = 0;
-    while i < arguments.len() {
-        let arg = arguments[i].clone();
-        if arg == static_key || arg == "-dn" || arg == "-non_shared" || arg == "-static" {
-            is_dynamic = false;
-        } else if arg == dynamic_key || arg == "-dy" || arg == "-call_shared" {
-            is_dynamic = true;
-        } else if arg == key && is_dynamic != dynamic_link {
-            if dynamic_link {
-                arguments.insert(i, dynamic_key.clone());
-                arguments.insert(i + 2, static_key.clone());
-            } else {
-                arguments.insert(i, static_key.clone());
-                arguments.insert(i + 2, dynamic_key.clone());
-            }
-            i += 2;
-        } else if let Some(path) = arg.strip_prefix("@") {
-            if let Some(res) = response_map.get_mut(path) {
-                let old_size = res.values.len();
-                is_dynamic = change_link_feature(
-                    key.clone(),
-                    is_linker.clone(),
-                    dynamic_link,
-                    is_dynamic,
-                    &mut res.values,
-                    // 不支持嵌套 ResponseFile
-                    &mut HashMap::new(),
-                );
-                if old_size != res.values.len() {
-                    res.changed = true;
-                }
-            }
-        }
-        i += 1;
-    }
-    is_dynamic
-}
-
-fn static_link_feature(key: String, is_linker: Option<String>, arg: &mut Configuration) {
-    change_link_feature(
-        key,
-        is_linker,
-        false,
-        true,
-        &mut arg.arguments,
-        &mut arg.response_map,
-    );
-}
-
-fn dynamic_link_feature(key: String, is_linker: Option<String>, arg: &mut Configuration) {
-    change_link_feature(
-        key,
-        is_linker,
-        true,
-        true,
-        &mut arg.arguments,
-        &mut arg.response_map,
-    );
-}
-
-fn remove_argument(
-    value: String,
-    before: Option<String>,
-    after: Option<String>,
-    args: &mut Vec<String>,
-    response_map: &mut HashMap<String, ResponseFile>,
-) -> Vec<String> {
-    let mut result: Vec<String> = vec![];
-    let mut i = 0;
-    while i < args.len() {
-        if let Some(path) = args[i].strip_prefix("@") {
-            if let Some(res) = response_map.get_mut(path) {
-                let elements = remove_argument(
-                    value.clone(),
-                    before.clone(),
-                    after.clone(),
-                    &mut res.values,
-                    &mut HashMap::new(),
-                );
-
-                result.append(
-                    &mut elements
-                        .into_iter()
-                        .filter(|item| !result.contains(item))
-                        .collect::<Vec<String>>(),
-                );
-            }
-        } else if args[i].ends_with(&value) {
-            // 通常用于移动静态库/动态库在开头或末尾,因此这里仅匹配结尾字符串
-            if let Some(ref before) = before {
-                if i > 1 && args[i - 1].ends_with(before) {
-                    let lib = args.remove(i);
-                    if !result.contains(&lib) {
-                        result.push(lib);
-                    }
-                    continue;
-                }
-            } else if let Some(ref after) = after {
-                if i < args.len() - 1 && args[i + 1].ends_with(after) {
-                    let lib = args.remove(i);
-                    if !result.contains(&lib) {
-                        result.push(lib);
-                    }
-                }
-            } else {
-                let lib = args.remove(i);
-                if !result.contains(&lib) {
-                    result.push(lib);
-                }
-            }
-        }
-        i += 1;
-    }
-    return result;
-}
-
-fn move_to_back_for_before_feature(value: String, before: Option<String>, arg: &mut Configuration) {
-    // 将匹配的指定参数移动到末尾
-    let mut result = remove_argument(
-        value.clone(),
-        before,
-        None,
-        &mut arg.arguments,
-        &mut arg.response_map,
-    );
-    arg.arguments.append(&mut result);
-}
-
-fn move_to_back_for_after_feature(value: String, after: Option<String>, arg: &mut Configuration) {
-    let mut result = remove_argument(
-        value.clone(),
-        None,
-        after,
-        &mut arg.arguments,
-        &mut arg.response_map,
-    );
-    arg.arguments.append(&mut result);
-}
-
-fn move_to_front_for_before_feature(
-    value: String,
-    before: Option<String>,
-    arg: &mut Configuration,
-) {
-    let result = remove_argument(
-        value.clone(),
-        before,
-        None,
-        &mut arg.arguments,
-        &mut arg.response_map,
-    );
-    arg.arguments.splice(0..0, result.into_iter());
-}
-
-fn move_to_front_for_after_feature(value: String, after: Option<String>, arg: &mut Configuration) {
-    let result = remove_argument(
-        value.clone(),
-        None,
-        after,
-        &mut arg.arguments,
-        &mut arg.response_map,
-    );
-    arg.arguments.splice(0..0, result.into_iter());
-}
-
-fn replace_argument_feature(key: String, value: Option<String>, arg: &mut Configuration) {
-    if let Some(value) = value {
-        for arg in arg.arguments.iter_mut() {
-            if arg == &key {
-                *arg = value.clone();
-            }
-        }
-        for (_, v) in arg.response_map.iter_mut() {
-            v.replace_value(&key, &value)
-        }
-    }
-}
-
-fn remove_argument_feature(key: String, _: Option<String>, arg: &mut Configuration) {
-    arg.arguments.retain(|item| item != &key);
-    for (_, v) in arg.response_map.iter_mut() {
-        v.remove_value(&key)
-    }
-}
-
-fn have_bool_environment_variable(key: &str) -> bool {
-    if let Ok(value) = env::var(key) {
-        let v = value.to_lowercase();
-        return v == "1" || v == "true" || v == "yes" || v == "on";
-    }
-    false
-}
-
-fn get_string_environment_variable(key: &str) -> String {
-    env::var(key).unwrap_or("".to_string())
-}
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-
-    #[test]
-    fn test_move_to_back_feature() {
-        let vec1: Vec<String> = vec![
-            "a0".to_owned(),
-            "prefix-a1".to_owned(),
-            "prefix-a2".to_owned(),
-            "a3".to_owned(),
-            "a1".to_owned(),
-            "a4".to_owned(),
-            "a5".to_owned(),
-        ];
-        let mut config = Configuration::new();
-        config.arguments = vec1.clone();
-        move_to_back_for_after_feature("a1".to_owned(), None, &mut config);
-        assert_eq!(
-            config.arguments,
-            vec![
-                "a0".to_owned(),
-                "prefix-a2".to_owned(),
-                "a3".to_owned(),
-                "a4".to_owned(),
-                "a5".to_owned(),
-                "prefix-a1".to_owned(),
-                "a1".to_owned(),
-            ]
-        );
-
-        config.arguments = vec1.clone();
-        move_to_back_for_after_feature("a1".to_owned(), Some("a2".to_owned()), &mut config);
-        assert_eq!(
-            config.arguments,
-            vec![
-                "a0".to_owned(),
-                "prefix-a2".to_owned(),
-                "a3".to_owned(),
-                "a1".to_owned(),
-                "a4".to_owned(),
-                "a5".to_owned(),
-                "prefix-a1".to_owned(),
-            ]
-        );
-
-        config.arguments = vec1.clone();
-        move_to_back_for_after_feature("a5".to_owned(), Some("after".to_owned()), &mut config);
-        assert_eq!(config.arguments, vec1);
-
-        config.arguments = vec1.clone();
-        move_to_back_for_before_feature("a1".to_owned(), Some("none".to_owned()), &mut config);
-        assert_eq!(config.arguments, vec1);
-
-        move_to_back_for_before_feature("a1".to_owned(), Some("a3".to_owned()), &mut config);
-        assert_eq!(
-            config.arguments,
-            vec![
-                "a0".to_owned(),
-                "prefix-a1".to_owned(),
-                "prefix-a2".to_owned(),
-                "a3".to_owned(),
-                "a4".to_owned(),
-                "a5".to_owned(),
-                "a1".to_owned(),
-            ]
-        );
-        config.arguments = vec1.clone();
-        move_to_back_for_before_feature("a0".to_owned(), Some("before".to_owned()), &mut config);
-        assert_eq!(config.arguments, vec1);
-    }
-
-    #[test]
-    fn test_move_to_before_feature() {
-        let vec1: Vec<String> = vec![
-            "a0".to_owned(),
-            "prefix-a1".to_owned(),
-            "prefix-a2".to_owned(),
-            "a3".to_owned(),
-            "a1".to_owned(),
-            "a4".to_owned(),
-            "a5".to_owned(),
-        ];
-        let mut config = Configuration::new();
-        config.arguments = vec1.clone();
-        move_to_front_for_after_feature("a1".to_owned(), None, &mut config);
-        assert_eq!(
-            config.arguments,
-            vec![
-                "prefix-a1".to_owned(),
-                "a1".to_owned(),
-                "a0".to_owned(),
-                "prefix-a2".to_owned(),
-                "a3".to_owned(),
-                "a4".to_owned(),
-                "a5".to_owned(),
-            ]
-        );
-
-        config.arguments = vec1.clone();
-        move_to_front_for_after_feature("a1".to_owned(), Some("a2".to_owned()), &mut config);
-        assert_eq!(
-            config.arguments,
-            vec![
-                "prefix-a1".to_owned(),
-                "a0".to_owned(),
-                "prefix-a2".to_owned(),
-                "a3".to_owned(),
-                "a1".to_owned(),
-                "a4".to_owned(),
-                "a5".to_owned(),
-            ]
-        );
-
-        config.arguments = vec1.clone();
-        move_to_front_for_after_feature("a5".to_owned(), Some("after".to_owned()), &mut config);
-        assert_eq!(config.arguments, vec1);
-
-        config.arguments = vec1.clone();
-        move_to_front_for_before_feature("a1".to_owned(), Some("none".to_owned()), &mut config);
-        assert_eq!(config.arguments, vec1);
-
-        move_to_front_for_before_feature("a1".to_owned(), Some("a3".to_owned()), &mut config);
-        assert_eq!(
-            config.arguments,
-            vec![
-                "a1".to_owned(),
-                "a0".to_owned(),
-                "prefix-a1".to_owned(),
-                "prefix-a2".to_owned(),
-                "a3".to_owned(),
-                "a4".to_owned(),
-                "a5".to_owned(),
-            ]
-        );
-        config.arguments = vec1.clone();
-        move_to_front_for_before_feature("a0".to_owned(), Some("before".to_owned()), &mut config);
-        assert_eq!(config.arguments, vec1);
-    }
-}
-
-struct CommandWrapper(
-    String,
-    Option<String>,
-    fn(String, Option<String>, &mut Configuration) -> (),
-);
-
-enum CommandType {
-    Flag,
-    Command(CommandWrapper),
-    // 需要一个参数
-    Option,
-    Ignore,
-}
-
-fn parse_arguments(config: &mut Configuration, key: &str) -> CommandType {
-    if key == "just-print" {
-        config.just_print = true;
-        CommandType::Flag
-    } else if key == "before-print" {
-        config.before_print = true;
-        CommandType::Flag
-    } else if let Some(log_file) = key.strip_prefix("log-file=") {
-        config.log_file = log_file.to_string();
-        CommandType::Option
-    } else if let Some(command) = key.strip_prefix("command=") {
-        config.command = command.to_string();
-        CommandType::Option
-    } else if let Some(dir) = key.strip_prefix("work-dir=") {
-        config.work_dir = dir.to_string();
-        CommandType::Option
-    } else if let Some(path) = key.strip_prefix("redirect-stdout=") {
-        config.redirect_stdout = path.to_string();
-        CommandType::Option
-    } else if let Some(path) = key.strip_prefix("redirect-stderr=") {
-        config.redirect_stderr = path.to_string();
-        CommandType::Option
-    } else if let Some(arg) = key.strip_prefix("remove=") {
-        CommandType::Command(CommandWrapper(
-            arg.to_string(),
-            None,
-            remove_argument_feature,
-        ))
-    } else if let Some(arg) = key.strip_prefix("replace-") {
-        let mut args = arg.splitn(2, '=');
-        let before = args.next().unwrap_or("");
-        let after = args.next().unwrap_or("");
-        if before.is_empty() || after.is_empty() {
-            CommandType::Ignore
-        } else {
-            CommandType::Command(CommandWrapper(
-                before.to_string(),
-                Some(after.to_string()),
-                replace_argument_feature,
-            ))
-        }
-    } else if let Some(lib) = key.strip_prefix("static-link-compiler=") {
-        CommandType::Command(CommandWrapper(lib.to_string(), None, static_link_feature))
-    } else if let Some(lib) = key.strip_prefix("dynamic-link-compiler=") {
-        CommandType::Command(CommandWrapper(lib.to_string(), None, dynamic_link_feature))
-    } else if let Some(lib) = key.strip_prefix("static-link=") {
-        CommandType::Command(CommandWrapper(
-            lib.to_string(),
-            Some("1".to_string()),
-            static_link_feature,
-        ))
-    } else if let Some(lib) = key.strip_prefix("dynamic-link=") {
-        CommandType::Command(CommandWrapper(
-            lib.to_string(),
-            Some("1".to_string()),
-            dynamic_link_feature,
-        ))
-    } else if let Some(value) = key.strip_prefix("move-front=") {
-        CommandType::Command(CommandWrapper(
-            value.to_string(),
-            None,
-            move_to_front_for_before_feature,
-        ))
-    } else if let Some(value) = key.strip_prefix("move-front-before-") {
-        let mut keys = value.splitn(2, '=');
-        let before = keys.next().unwrap_or("");
-        let value = keys.next().unwrap_or("");
-        if before.is_empty() || value.is_empty() {
-            CommandType::Ignore
-        } else {
-            CommandType::Command(CommandWrapper(
-                value.to_string(),
-                Some(before.to_string()),
-                move_to_front_for_before_feature,
-            ))
-        }
-    } else if let Some(value) = key.strip_prefix("move-front-after-") {
-        let mut keys = value.splitn(2, '=');
-        let after = keys.next().unwrap_or("");
-        let value = keys.next().unwrap_or("");
-        if after.is_empty() || value.is_empty() {
-            CommandType::Ignore
-        } else {
-            CommandType::Command(CommandWrapper(
-                value.to_string(),
-                Some(after.to_string()),
-                move_to_front_for_after_feature,
-            ))
-        }
-    } else if let Some(value) = key.strip_prefix("move-back=") {
-        CommandType::Command(CommandWrapper(
-            value.to_string(),
-            None,
-            move_to_back_for_before_feature,
-        ))
-    } else if let Some(value) = key.strip_prefix("move-back-before-") {
-        let mut keys = value.splitn(2, '=');
-        let before = keys.next().unwrap_or("");
-        let value = keys.next().unwrap_or("");
-        if before.is_empty() || value.is_empty() {
-            CommandType::Ignore
-        } else {
-            CommandType::Command(CommandWrapper(
-                value.to_string(),
-                Some(before.to_string()),
-                move_to_back_for_before_feature,
-            ))
-        }
-    } else if let Some(value) = key.strip_prefix("move-back-after-") {
-        let mut keys = value.splitn(2, '=');
-        let after = keys.next().unwrap_or("");
-        let value = keys.next().unwrap_or("");
-        if after.is_empty() || value.is_empty() {
-            CommandType::Ignore
-        } else {
-            CommandType::Command(CommandWrapper(
-                value.to_string(),
-                Some(after.to_string()),
-                move_to_back_for_after_feature,
-            ))
-        }
-    } else {
-        CommandType::Ignore
-    }
-}
+const MAX_DEPTH: u32 = 8;
 
 fn run() -> Result<i32> {
     let mut config = Configuration::new();
@@ -702,6 +28,9 @@ fn run() -> Result<i32> {
         .to_path_buf()
         .to_string_lossy()
         .to_string();
+    // 通过 install 创建的符号链接调用时, 使用调用的路径查找对应工具的配置
+    let multi_call = install::invoked_path(&exe);
+    let exe = multi_call.clone().unwrap_or(exe);
 
     // 默认可以走替换模式
     if config.command.is_empty() {
@@ -717,21 +46,15 @@ fn run() -> Result<i32> {
 
     let prefix = "-clw-";
     let mut commands = vec![];
+    let mut settings = vec![];
+    let mut unknown = vec![];
+    let mut invalid = vec![];
     let mut start_index = 1;
-    if config.command.is_empty() {
-        if env::args().len() < 2 {
-            eprintln!("wrapper mode runs but no wrapper command is available");
-            return Ok(1);
-        }
-        config.command = env::args().nth(1).unwrap();
-        start_index = 2;
-    }
-
     // 有些命令被驱动时可能没有环境变量,因此再增加配置文件读取,配置文件每一行一个命令
     let config_file_path = if let Some(value) = exe.strip_suffix(".exe") {
         value.to_owned() + "-clw-config.txt"
     } else {
-        exe + "-clw-config.txt"
+        exe.clone() + "-clw-config.txt"
     };
 
     let config_file = Path::new(&config_file_path);
@@ -741,29 +64,104 @@ fn run() -> Result<i32> {
         for argument in content.lines() {
             if let Some(key) = argument.strip_prefix(prefix) {
                 match parse_arguments(&mut config, key) {
-                    CommandType::Command(f) => commands.push(f),
+                    CommandType::Command(f) => commands.push((argument.to_string(), f)),
                     CommandType::Ignore => {
+                        unknown.push(argument.to_string());
                         config.arguments.push(argument.to_string());
                     }
-                    _ => {}
+                    CommandType::Invalid(message) => invalid.push(message),
+                    _ => settings.push(argument.to_string()),
+                }
+            }
+        }
+    }
+
+    // 没有配置被包装的命令时支持子命令, 未知的子命令作为被包装的命令.
+    // 配置了命令或者通过符号链接调用时, 所有参数都传给被包装的命令
+    if config.command.is_empty() && multi_call.is_none() {
+        let subcommand = env::args().nth(1).unwrap_or_default();
+        let usage = match subcommand.as_str() {
+            "run" => Some(cli::RUN_USAGE),
+            "print" => Some(cli::PRINT_USAGE),
+            "explain" => Some(cli::EXPLAIN_USAGE),
+            _ => None,
+        };
+        match subcommand.as_str() {
+            "" => {
+                eprintln!("{}", cli::USAGE);
+                return Ok(1);
+            }
+            "help" | "-h" | "--help" => {
+                println!("{}", cli::USAGE);
+                println!("\nRules:");
+                for rule in rule::builtin().rules() {
+                    println!("  {}", rule.describe());
+                }
+                return Ok(0);
+            }
+            "version" | "-V" | "--version" => {
+                if matches!(env::args().nth(2).as_deref(), Some("-h" | "--help")) {
+                    println!("{}", cli::VERSION_USAGE);
+                } else {
+                    println!("{}", cli::version());
+                }
+                return Ok(0);
+            }
+            "report" => {
+                init_log("");
+                return report::report_command(env::args().skip(2));
+            }
+            "install" => {
+                init_log("");
+                return install::install_command(env::args().skip(2));
+            }
+            "check-config" => {
+                init_log("");
+                return check::check_config_command(env::args().skip(2));
+            }
+            _ => {}
+        }
+        if let Some(usage) = usage {
+            match env::args().nth(2) {
+                Some(command) if command != "-h" && command != "--help" => {
+                    config.command = command;
+                    config.just_print |= subcommand == "print";
+                    config.explain |= subcommand == "explain";
+                    start_index = 3;
+                }
+                Some(_) => {
+                    println!("{}", usage);
+                    return Ok(0);
+                }
+                None => {
+                    eprintln!("{}", usage);
+                    return Ok(1);
                 }
             }
+        } else {
+            config.command = subcommand;
+            start_index = 2;
         }
     }
 
     // 初始化 log
     init_log(config.log_file.as_str());
+    // 符号链接模式下没有配置真实工具时, 在 PATH 中查找同名的工具
+    if config.command.is_empty() && multi_call.is_some() {
+        config.command = report::tool_name(&exe);
+    }
 
     {
-        let mut iter = env::args().skip(start_index);
-        while let Some(argument) = iter.next() {
+        for argument in env::args().skip(start_index) {
             if let Some(key) = argument.strip_prefix(prefix) {
                 match parse_arguments(&mut config, key) {
-                    CommandType::Command(f) => commands.push(f),
+                    CommandType::Command(f) => commands.push((argument, f)),
                     CommandType::Ignore => {
+                        unknown.push(argument.clone());
                         config.arguments.push(argument);
                     }
-                    _ => {}
+                    CommandType::Invalid(message) => invalid.push(message),
+                    _ => settings.push(argument),
                 }
             } else if let Some(response_file) = argument.strip_prefix("@") {
                 let path = Path::new(response_file);
@@ -789,57 +187,374 @@ fn run() -> Result<i32> {
         }
     }
 
-    for c in commands {
-        c.2(c.0, c.1, &mut config);
+    if !invalid.is_empty() {
+        for message in invalid {
+            error!("{}", message);
+        }
+        return Ok(1);
     }
 
-    config.replace_response_file()?;
+    if config.explain {
+        println!("command: {}", config.command);
+        for setting in settings.iter() {
+            println!("setting: {}", setting);
+        }
+        for rule in config.exit_rules.iter() {
+            println!("exit: {}", rule.describe());
+        }
+        for step in config.output_steps.iter() {
+            println!("output: {}", step.describe());
+        }
+        for argument in unknown.iter() {
+            println!("unknown: {} (forwarded to the command)", argument);
+        }
+    }
+    for (rule, c) in commands {
+        let before = config.explain.then(|| config.expanded_arguments());
+        c.apply(&mut config)?;
+        if let Some(before) = before {
+            cli::explain_rule(&rule, &before, &config.expanded_arguments());
+        }
+    }
+    reproducible_feature(&mut config);
 
-    if config.just_print || config.before_print {
-        warn!("{} {}", config.command, config.arguments.join(" "));
+    if config.explain {
+        println!(
+            "final: {} {}",
+            config.command,
+            config.expanded_arguments().join(" ")
+        );
+        return Ok(0);
     }
+    config.replace_response_file()?;
+
     if config.just_print {
+        warn!("{} {}", config.command, config.arguments.join(" "));
         return Ok(0);
     }
 
     let mut code = 1;
 
+    if tool::is_current_exe(&config.command) {
+        match tool::find_real_program(&config.command) {
+            Some(path) => config.command = path.to_string_lossy().into_owned(),
+            None => bail!(
+                "{} is cli-wrapper itself, refusing to wrap it recursively",
+                config.command
+            ),
+        }
+    }
+    // 被包装的工具再次调用 wrapper 时深度加一, 超过限制说明存在循环调用
+    let depth: u32 = env::var("CLW_DEPTH")
+        .unwrap_or_default()
+        .parse()
+        .unwrap_or(0);
+    if depth >= MAX_DEPTH {
+        bail!(
+            "cli-wrapper is nested {} times (CLW_DEPTH), {} probably invokes the wrapper recursively",
+            depth,
+            config.command
+        );
+    }
+    hook::run_hooks(&config, Phase::Pre)?;
     let mut command = Command::new(&config.command);
-    command.args(&config.arguments);
+    for key in config.removed_environment.iter() {
+        command.env_remove(key);
+    }
+    command.envs(config.environment.iter().map(|(k, v)| (k, v)));
+    command.env("CLW_DEPTH", (depth + 1).to_string());
     if !config.work_dir.is_empty() {
         command.current_dir(&config.work_dir);
     }
 
+    let mut stdout_file = None;
+    let mut stderr_file = None;
     if config.redirect_stdout == config.redirect_stderr && !config.redirect_stdout.is_empty() {
         let output = File::create(&config.redirect_stdout)?;
-        let error = output.try_clone()?;
-        command.stdout(Stdio::from(output));
-        command.stderr(Stdio::from(error));
+        stderr_file = Some(output.try_clone()?);
+        stdout_file = Some(output);
     } else {
         if !config.redirect_stdout.is_empty() {
-            let output = File::create(&config.redirect_stdout)?;
-            command.stdout(Stdio::from(output));
+            stdout_file = Some(File::create(&config.redirect_stdout)?);
         }
         if !config.redirect_stderr.is_empty() {
-            let error = File::create(&config.redirect_stderr)?;
-            command.stderr(Stdio::from(error));
+            stderr_file = Some(File::create(&config.redirect_stderr)?);
+        }
+    }
+    let mut filter = DiagnosticFilter::new(&config.stderr_filters, &config.stderr_rewrites);
+
+    let start = Instant::now();
+    let mut cache_state = None;
+    let mut cache_job = None;
+    let mut cached = None;
+    if !config.cache_dir.is_empty() {
+        let cache = cache::Cache::new(&config.cache_dir, config.cache_max_size);
+        match cache.lookup(
+            &config.command,
+            &config.work_dir,
+            &config.expanded_arguments(),
+        )? {
+            cache::Lookup::Hit(result) => {
+                cache_state = Some("hit");
+                cached = Some(result);
+            }
+            cache::Lookup::Miss(job) => {
+                cache_state = Some("miss");
+                cache_job = Some((cache, job));
+            }
+            cache::Lookup::Bypass(reason) => {
+                debug!("cache bypassed: {}", reason);
+                cache_state = Some("bypass");
+            }
         }
     }
 
-    match command.spawn() {
-        Ok(mut child) => {
-            let exit_status = child.wait().expect("Failed to wait for child");
-            code = exit_status.code().unwrap_or(4);
+    // 缓存使用原始的参数查找, 因此在查找之后再改写输出参数, 命中缓存时同样先写入临时文件
+    let mut atomic = if config.atomic_output {
+        atomic::prepare(&mut config)?
+    } else {
+        None
+    };
+    // 命令行过长时使用响应文件传递参数, 命令结束后删除
+    let command_file = match cached {
+        Some(_) => None,
+        None => cmdline::prepare(&config)?,
+    };
+    let arguments = match &command_file {
+        Some(file) => vec![format!("@{}", file.path.to_string_lossy())],
+        None => config.arguments.clone(),
+    };
+    // 输出最终执行的参数, 包括临时的输出文件和响应文件
+    if config.before_print {
+        warn!("{} {}", config.command, arguments.join(" "));
+    }
+
+    let result = match &cached {
+        // 缓存中保存的是原始的输出, 按照当前的规则过滤
+        Some(cached) => {
+            let mut plain = DiagnosticFilter::default();
+            match stdout_file {
+                Some(file) => diagnostic::forward(&cached.stdout[..], file, &mut plain),
+                None => diagnostic::forward(&cached.stdout[..], std::io::stdout(), &mut plain),
+            };
+            match stderr_file {
+                Some(file) => diagnostic::forward(&cached.stderr[..], file, &mut filter),
+                None => diagnostic::forward(&cached.stderr[..], std::io::stderr(), &mut filter),
+            };
+            let usage = report::ResourceUsage {
+                wall_time: start.elapsed(),
+                user_time: None,
+                system_time: None,
+                max_rss: None,
+            };
+            Some((
+                cached.code,
+                cached.stdout.clone(),
+                cached.stderr.clone(),
+                usage,
+            ))
         }
-        Err(e) => {
-            error!("Failed to execute command: {}", e);
+        None => {
+            command.args(&arguments);
+
+            // 缓存需要保存 stdout 和 stderr, 过滤 stderr 或者根据输出修改退出码时同样需要捕获
+            let capture_output = !config.exit_rules.is_empty() || cache_job.is_some();
+            let stdout_writer = output_writer(
+                stdout_file,
+                capture_output,
+                Box::new(std::io::stdout()),
+                |stdio| {
+                    command.stdout(stdio);
+                },
+            );
+            let stderr_writer = output_writer(
+                stderr_file,
+                capture_output || !filter.is_empty(),
+                Box::new(std::io::stderr()),
+                |stdio| {
+                    command.stderr(stdio);
+                },
+            );
+
+            match command.spawn() {
+                Ok(mut child) => {
+                    if let Some(atomic) = &atomic {
+                        atomic.watch_child(child.id());
+                    }
+                    let stdout_reader = child.stdout.take().map(|stdout| {
+                        thread::spawn(move || {
+                            diagnostic::forward(
+                                stdout,
+                                stdout_writer,
+                                &mut DiagnosticFilter::default(),
+                            )
+                        })
+                    });
+                    let stderr_reader = child.stderr.take().map(|stderr| {
+                        thread::spawn(move || {
+                            diagnostic::forward(stderr, stderr_writer, &mut filter)
+                        })
+                    });
+                    let waited = report::wait_child(child, start);
+                    let join = |reader: Option<thread::JoinHandle<Vec<u8>>>| {
+                        reader
+                            .map(|reader| reader.join().unwrap_or_default())
+                            .unwrap_or_default()
+                    };
+                    let stdout = join(stdout_reader);
+                    let stderr = join(stderr_reader);
+                    match waited {
+                        Ok((exit_status, usage)) => {
+                            Some((exit_status.code().unwrap_or(4), stdout, stderr, usage))
+                        }
+                        // 之后删除临时文件并恢复参数
+                        Err(e) => {
+                            error!("Failed to wait for the command: {}", e);
+                            None
+                        }
+                    }
+                }
+                Err(e) => {
+                    error!("Failed to execute command: {}", e);
+                    None
+                }
+            }
         }
+    };
+
+    // 命中缓存和真正执行命令之后使用相同的方式处理退出码和输出文件
+    if let Some((status, stdout, stderr, usage)) = result {
+        code = status::override_exit_code(&config.exit_rules, status, &stdout, &stderr);
+        if let (Some(cached), 0) = (&cached, code) {
+            if let Err(e) = config
+                .output_path()
+                .and_then(|output| cached.restore(&config.work_dir, &output))
+            {
+                error!("Failed to restore the cached output: {}", e);
+                code = 1;
+            }
+        }
+        let mut verified = true;
+        if code == 0 && !config.verifications.is_empty() {
+            if let Err(e) = verify::verify_output(&config) {
+                error!("{}", e);
+                code = 1;
+                verified = false;
+            }
+        }
+        // 命令本身成功时就保存, 退出码规则在命中时重新应用. 在重命名之前保存, 此时输出参数指向实际写入的文件
+        if let Some((cache, job)) = cache_job {
+            if status == 0 && verified {
+                if let Err(e) = config.output_path().and_then(|output| {
+                    cache.store(&job, &output, &config.work_dir, status, &stdout, &stderr)
+                }) {
+                    warn!("Failed to store cache entry: {}", e);
+                }
+            }
+        }
+        if let Some(atomic) = atomic.take() {
+            if let Err(e) = atomic.commit(&mut config, code == 0) {
+                error!("Failed to rename the output: {}", e);
+                code = 1;
+            }
+        }
+        if code == 0 {
+            if let Err(e) =
+                depfile::rewrite_depfile(&config).and_then(|_| output::process_output(&config))
+            {
+                error!("{}", e);
+                code = 1;
+            }
+        }
+        log_resource_usage(&config, code, &usage, cache_state);
+    }
+    if let Some(atomic) = atomic {
+        atomic.commit(&mut config, false)?;
+    }
+    if let Err(e) = hook::run_hooks(&config, Phase::Post(code)) {
+        error!("{}", e);
+        code = 1;
     }
     Ok(code)
 }
 
+// 需要捕获输出时由读取线程写入终端或者重定向的文件
+fn output_writer(
+    file: Option<File>,
+    pipe: bool,
+    terminal: Box<dyn Write + Send>,
+    set: impl FnOnce(Stdio),
+) -> Box<dyn Write + Send> {
+    match (file, pipe) {
+        (Some(file), true) => {
+            set(Stdio::piped());
+            Box::new(file)
+        }
+        (Some(file), false) => {
+            set(Stdio::from(file));
+            terminal
+        }
+        (None, true) => {
+            set(Stdio::piped());
+            terminal
+        }
+        (None, false) => terminal,
+    }
+}
+
+fn log_resource_usage(
+    config: &Configuration,
+    code: i32,
+    usage: &report::ResourceUsage,
+    cache: Option<&str>,
+) {
+    let seconds = |d: Option<std::time::Duration>| {
+        d.map_or("-".to_string(), |d| format!("{:.3}s", d.as_secs_f64()))
+    };
+    let message = format!(
+        "{} exited with {}{}: wall {:.3}s, user {}, sys {}, max rss {}",
+        config.command,
+        code,
+        cache.map_or(String::new(), |c| format!(" (cache {})", c)),
+        usage.wall_time.as_secs_f64(),
+        seconds(usage.user_time),
+        seconds(usage.system_time),
+        usage
+            .max_rss
+            .map_or("-".to_string(), |rss| format!("{}KiB", rss)),
+    );
+    // 终端模式下避免每次调用都输出统计信息
+    if config.log_file.is_empty() {
+        debug!("{}", message);
+    } else {
+        info!("{}", message);
+    }
+
+    if !config.json_log.is_empty() {
+        let work_dir = if config.work_dir.is_empty() {
+            env::current_dir()
+                .map(|p| p.to_string_lossy().into_owned())
+                .unwrap_or_default()
+        } else {
+            config.work_dir.clone()
+        };
+        let record = report::InvocationRecord::new(
+            &config.command,
+            &config.arguments,
+            &work_dir,
+            config.output_file(),
+            code,
+            usage,
+            cache,
+        );
+        if let Err(e) = record.append_to(&config.json_log) {
+            error!("Failed to write json log {}: {}", config.json_log, e);
+        }
+    }
+}
+
 fn init_log(log_file: &str) {
-    let log_level = get_string_environment_variable("RUST_LOG").to_lowercase();
+    let log_level = env::var("RUST_LOG").unwrap_or_default().to_lowercase();
     let level = match log_level.as_str() {
         "off" => LevelFilter::Off,
         "debug" => LevelFilter::Debug,
@@ -0,0 +1,177 @@
+use anyhow::{anyhow, bail, Result};
+use sha2::{Digest, Sha224, Sha256, Sha384, Sha512};
+use simplelog::debug;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::Configuration;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Checksum {
+    Sha224,
+    Sha256,
+    Sha384,
+    Sha512,
+}
+
+impl Checksum {
+    pub fn parse(name: &str) -> Option<Checksum> {
+        match name.to_lowercase().as_str() {
+            "sha224" => Some(Checksum::Sha224),
+            "sha256" => Some(Checksum::Sha256),
+            "sha384" => Some(Checksum::Sha384),
+            "sha512" => Some(Checksum::Sha512),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Checksum::Sha224 => "sha224",
+            Checksum::Sha256 => "sha256",
+            Checksum::Sha384 => "sha384",
+            Checksum::Sha512 => "sha512",
+        }
+    }
+
+    fn digest(self, data: &[u8]) -> String {
+        let digest = match self {
+            Checksum::Sha224 => Sha224::digest(data).to_vec(),
+            Checksum::Sha256 => Sha256::digest(data).to_vec(),
+            Checksum::Sha384 => Sha384::digest(data).to_vec(),
+            Checksum::Sha512 => Sha512::digest(data).to_vec(),
+        };
+        digest.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+}
+
+// 命令成功后按照参数的顺序依次处理输出文件
+pub enum OutputStep {
+    Strip(String),
+    // 写入 <output>.<algo>, 格式和 sha256sum 等工具相同
+    Checksum(Checksum),
+    // 复制输出文件和之前生成的校验文件
+    Copy(String),
+}
+
+impl OutputStep {
+    pub fn describe(&self) -> String {
+        match self {
+            OutputStep::Strip(tool) => format!("strip the output with {}", tool),
+            OutputStep::Checksum(checksum) => {
+                format!("write the {} checksum of the output", checksum.name())
+            }
+            OutputStep::Copy(dir) => format!("copy the output to {}", dir),
+        }
+    }
+}
+
+fn strip(config: &Configuration, tool: &str, output: &Path) -> Result<()> {
+    let mut command = Command::new(tool);
+    command.arg(output);
+    for key in config.removed_environment.iter() {
+        command.env_remove(key);
+    }
+    command.envs(config.environment.iter().map(|(k, v)| (k, v)));
+    if !config.work_dir.is_empty() {
+        command.current_dir(&config.work_dir);
+    }
+    let status = command
+        .status()
+        .map_err(|e| anyhow!("failed to execute {}: {}", tool, e))?;
+    if !status.success() {
+        bail!(
+            "{} {} exited with {}",
+            tool,
+            output.to_string_lossy(),
+            status.code().unwrap_or(-1)
+        );
+    }
+    Ok(())
+}
+
+fn write_checksum(checksum: Checksum, output: &Path) -> Result<PathBuf> {
+    let digest = checksum.digest(&fs::read(output)?);
+    let name = output.file_name().unwrap_or_default().to_string_lossy();
+    let sidecar = PathBuf::from(format!("{}.{}", output.to_string_lossy(), checksum.name()));
+    fs::write(&sidecar, format!("{}  {}\n", digest, name))?;
+    Ok(sidecar)
+}
+
+fn copy_to(dir: &Path, files: &[PathBuf]) -> Result<()> {
+    fs::create_dir_all(dir)?;
+    for file in files {
+        // 先复制为临时文件再重命名, 避免其它进程读到不完整的文件
+        let target = dir.join(file.file_name().unwrap_or_default());
+        let temp = dir.join(format!(
+            ".{}.clw-{}",
+            target.file_name().unwrap_or_default().to_string_lossy(),
+            std::process::id()
+        ));
+        fs::copy(file, &temp)?;
+        fs::rename(&temp, &target)?;
+    }
+    Ok(())
+}
+
+pub fn process_output(config: &Configuration) -> Result<()> {
+    if config.output_steps.is_empty() {
+        return Ok(());
+    }
+    let output = config.output_path()?;
+    if !output.is_file() {
+        bail!("cannot find the output {}", output.to_string_lossy());
+    }
+    let mut files = vec![output.clone()];
+    for step in config.output_steps.iter() {
+        match step {
+            OutputStep::Strip(tool) => strip(config, tool, &output)?,
+            OutputStep::Checksum(checksum) => files.push(write_checksum(*checksum, &output)?),
+            OutputStep::Copy(dir) => copy_to(&config.resolve_path(dir)?, &files)?,
+        }
+        debug!("{}: {}", output.to_string_lossy(), step.describe());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    #[test]
+    fn test_process_output() {
+        let dir = env::temp_dir().join(format!("clw_output_{}", std::process::id()));
+        fs::create_dir_all(dir.join("obj")).unwrap();
+        fs::write(dir.join("obj/a.obj"), "abc").unwrap();
+
+        let mut config = Configuration::new();
+        config.command = "cl.exe".to_owned();
+        config.work_dir = dir.to_string_lossy().into_owned();
+        config.arguments = ["/c", "/Foobj/", "a.c"].map(String::from).to_vec();
+        config.output_steps = vec![
+            OutputStep::Checksum(Checksum::Sha256),
+            OutputStep::Copy("staging".to_owned()),
+        ];
+        process_output(&config).unwrap();
+        let sidecar = "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad  a.obj\n";
+        assert_eq!(
+            fs::read_to_string(dir.join("obj/a.obj.sha256")).unwrap(),
+            sidecar
+        );
+        assert_eq!(
+            fs::read_to_string(dir.join("staging/a.obj")).unwrap(),
+            "abc"
+        );
+        assert_eq!(
+            fs::read_to_string(dir.join("staging/a.obj.sha256")).unwrap(),
+            sidecar
+        );
+
+        config.arguments = ["/c", "/Fomissing.obj", "a.c"].map(String::from).to_vec();
+        assert!(process_output(&config).is_err());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,383 @@
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::process::{Child, ExitStatus};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+pub struct ResourceUsage {
+    pub wall_time: Duration,
+    pub user_time: Option<Duration>,
+    pub system_time: Option<Duration>,
+    // 单位 KiB
+    pub max_rss: Option<u64>,
+}
+
+#[cfg(unix)]
+pub fn wait_child(child: Child, start: Instant) -> Result<(ExitStatus, ResourceUsage)> {
+    use std::os::unix::process::ExitStatusExt;
+
+    let pid = child.id() as libc::pid_t;
+    let mut status: libc::c_int = 0;
+    let mut rusage: libc::rusage = unsafe { std::mem::zeroed() };
+    loop {
+        let ret = unsafe { libc::wait4(pid, &mut status, 0, &mut rusage) };
+        if ret == pid {
+            break;
+        }
+        let err = std::io::Error::last_os_error();
+        if err.kind() != std::io::ErrorKind::Interrupted {
+            return Err(err.into());
+        }
+    }
+    let wall_time = start.elapsed();
+
+    let timeval = |tv: libc::timeval| {
+        Duration::from_secs(tv.tv_sec as u64) + Duration::from_micros(tv.tv_usec as u64)
+    };
+    // macOS 上 ru_maxrss 单位是字节, 其它平台为 KiB
+    let max_rss = if cfg!(target_vendor = "apple") {
+        rusage.ru_maxrss as u64 / 1024
+    } else {
+        rusage.ru_maxrss as u64
+    };
+    Ok((
+        ExitStatus::from_raw(status),
+        ResourceUsage {
+            wall_time,
+            user_time: Some(timeval(rusage.ru_utime)),
+            system_time: Some(timeval(rusage.ru_stime)),
+            max_rss: Some(max_rss),
+        },
+    ))
+}
+
+#[cfg(not(unix))]
+pub fn wait_child(mut child: Child, start: Instant) -> Result<(ExitStatus, ResourceUsage)> {
+    let status = child.wait()?;
+    Ok((
+        status,
+        ResourceUsage {
+            wall_time: start.elapsed(),
+            user_time: None,
+            system_time: None,
+            max_rss: None,
+        },
+    ))
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct InvocationRecord {
+    pub timestamp: u64,
+    pub tool: String,
+    pub command: String,
+    pub arguments: Vec<String>,
+    pub work_dir: String,
+    pub output: Option<String>,
+    pub exit_code: i32,
+    pub wall_time: f64,
+    pub user_time: Option<f64>,
+    pub system_time: Option<f64>,
+    pub max_rss: Option<u64>,
+    pub cache: Option<String>,
+}
+
+impl InvocationRecord {
+    pub fn new(
+        command: &str,
+        arguments: &[String],
+        work_dir: &str,
+        output: Option<String>,
+        exit_code: i32,
+        usage: &ResourceUsage,
+        cache: Option<&str>,
+    ) -> InvocationRecord {
+        InvocationRecord {
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0),
+            tool: tool_name(command),
+            command: command.to_string(),
+            arguments: arguments.to_vec(),
+            work_dir: work_dir.to_string(),
+            output,
+            exit_code,
+            wall_time: usage.wall_time.as_secs_f64(),
+            user_time: usage.user_time.map(|d| d.as_secs_f64()),
+            system_time: usage.system_time.map(|d| d.as_secs_f64()),
+            max_rss: usage.max_rss,
+            cache: cache.map(|c| c.to_string()),
+        }
+    }
+
+    // 每条记录一行 json, 多个进程同时追加时单次 write 不会交错
+    pub fn append_to(&self, path: &str) -> Result<()> {
+        let mut line = serde_json::to_string(self)?;
+        line.push('\n');
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)?
+            .write_all(line.as_bytes())?;
+        Ok(())
+    }
+}
+
+pub fn tool_name(command: &str) -> String {
+    let name = command.rsplit(['/', '\\']).next().unwrap_or(command);
+    name.strip_suffix(".exe").unwrap_or(name).to_string()
+}
+
+#[derive(Default)]
+struct Summary {
+    count: usize,
+    failures: usize,
+    wall_total: f64,
+    wall_max: f64,
+    user_total: f64,
+    system_total: f64,
+    max_rss: u64,
+}
+
+impl Summary {
+    fn add(&mut self, record: &InvocationRecord) {
+        self.count += 1;
+        if record.exit_code != 0 {
+            self.failures += 1;
+        }
+        self.wall_total += record.wall_time;
+        self.wall_max = self.wall_max.max(record.wall_time);
+        self.user_total += record.user_time.unwrap_or(0.0);
+        self.system_total += record.system_time.unwrap_or(0.0);
+        self.max_rss = self.max_rss.max(record.max_rss.unwrap_or(0));
+    }
+}
+
+const REPORT_USAGE: &str = "\
+Usage: cli-wrapper report [options] <json log>...
+
+Aggregate the records written by -clw-json-log into a table.
+
+Options:
+  --group-by <output|tool>         Group records by output file or by tool (default: output)
+  --sort <wall|user|sys|rss|count> Sort column (default: wall)
+  --top <n>                        Number of rows to print (default: 20)
+  -h, --help                       Print this help";
+
+pub fn report_command(args: impl Iterator<Item = String>) -> Result<i32> {
+    let mut group_by = "output".to_string();
+    let mut sort = "wall".to_string();
+    let mut top = 20;
+    let mut files = vec![];
+
+    let mut args = args;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-h" | "--help" => {
+                println!("{}", REPORT_USAGE);
+                return Ok(0);
+            }
+            "--group-by" | "--sort" | "--top" => {
+                let Some(value) = args.next() else {
+                    bail!("missing value for {}", arg);
+                };
+                match arg.as_str() {
+                    "--group-by" => group_by = value,
+                    "--sort" => sort = value,
+                    _ => match value.parse() {
+                        Ok(value) => top = value,
+                        Err(_) => bail!("invalid --top '{}', expected a number", value),
+                    },
+                }
+            }
+            _ => files.push(arg),
+        }
+    }
+    if files.is_empty() {
+        eprintln!("{}", REPORT_USAGE);
+        return Ok(1);
+    }
+    if group_by != "output" && group_by != "tool" {
+        bail!("unknown group '{}', expected output or tool", group_by);
+    }
+    if !["wall", "user", "sys", "rss", "count"].contains(&sort.as_str()) {
+        bail!(
+            "unknown sort column '{}', expected wall, user, sys, rss or count",
+            sort
+        );
+    }
+
+    print!("{}", report(&files, &group_by, &sort, top)?);
+    Ok(0)
+}
+
+// 按照 group_by 汇总所有记录, 按照 sort 列降序排列并保留前 top 行
+fn report(files: &[String], group_by: &str, sort: &str, top: usize) -> Result<String> {
+    use std::fmt::Write;
+
+    let mut groups: HashMap<String, Summary> = HashMap::new();
+    let mut cache: HashMap<String, usize> = HashMap::new();
+    for file in files.iter() {
+        for (index, line) in fs::read_to_string(file)?.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let record: InvocationRecord = match serde_json::from_str(line) {
+                Ok(record) => record,
+                Err(e) => bail!("{}:{}: invalid record: {}", file, index + 1, e),
+            };
+            let key = if group_by == "tool" {
+                record.tool.clone()
+            } else {
+                record.output.clone().unwrap_or_else(|| record.tool.clone())
+            };
+            groups.entry(key).or_default().add(&record);
+            if let Some(ref state) = record.cache {
+                *cache.entry(state.clone()).or_default() += 1;
+            }
+        }
+    }
+
+    let mut rows: Vec<(String, Summary)> = groups.into_iter().collect();
+    let metric = |s: &Summary| -> f64 {
+        match sort {
+            "user" => s.user_total,
+            "sys" => s.system_total,
+            "rss" => s.max_rss as f64,
+            "count" => s.count as f64,
+            _ => s.wall_total,
+        }
+    };
+    rows.sort_by(|a, b| metric(&b.1).total_cmp(&metric(&a.1)).then(a.0.cmp(&b.0)));
+    rows.truncate(top);
+
+    let mut text = String::new();
+    writeln!(
+        text,
+        "{:>6} {:>6} {:>10} {:>10} {:>10} {:>10} {:>12}  {}",
+        "count", "failed", "wall(s)", "max(s)", "user(s)", "sys(s)", "maxrss(KiB)", group_by
+    )?;
+    for (key, s) in rows.iter() {
+        writeln!(
+            text,
+            "{:>6} {:>6} {:>10.3} {:>10.3} {:>10.3} {:>10.3} {:>12}  {}",
+            s.count,
+            s.failures,
+            s.wall_total,
+            s.wall_max,
+            s.user_total,
+            s.system_total,
+            s.max_rss,
+            key
+        )?;
+    }
+    if !cache.is_empty() {
+        let hits = cache.get("hit").copied().unwrap_or(0);
+        let misses = cache.get("miss").copied().unwrap_or(0);
+        let bypasses = cache.get("bypass").copied().unwrap_or(0);
+        writeln!(
+            text,
+            "\ncache: {} hits, {} misses, {} bypassed ({:.1}% hit rate)",
+            hits,
+            misses,
+            bypasses,
+            hits as f64 * 100.0 / (hits + misses).max(1) as f64
+        )?;
+    }
+    Ok(text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    #[test]
+    fn test_record_round_trip() {
+        let usage = ResourceUsage {
+            wall_time: Duration::from_millis(1500),
+            user_time: Some(Duration::from_secs(1)),
+            system_time: None,
+            max_rss: Some(2048),
+        };
+        let record = InvocationRecord::new(
+            "/usr/bin/gcc.exe",
+            &["-c".to_owned(), "a.c".to_owned()],
+            "/tmp",
+            Some("a.o".to_owned()),
+            0,
+            &usage,
+            Some("miss"),
+        );
+        assert_eq!(record.tool, "gcc");
+        let line = serde_json::to_string(&record).unwrap();
+        let parsed: InvocationRecord = serde_json::from_str(&line).unwrap();
+        assert_eq!(parsed.output.as_deref(), Some("a.o"));
+        assert_eq!(parsed.wall_time, 1.5);
+        assert_eq!(parsed.system_time, None);
+        assert_eq!(parsed.max_rss, Some(2048));
+        assert_eq!(parsed.cache.as_deref(), Some("miss"));
+    }
+
+    #[test]
+    fn test_report() {
+        let dir = env::temp_dir().join(format!("clw_report_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let log = dir.join("log.json");
+        let record = |tool: &str, output: &str, code: i32, wall: f64, rss: u64, cache: &str| {
+            format!(
+                r#"{{"timestamp":0,"tool":"{}","command":"{}","arguments":[],"work_dir":"/tmp","output":"{}","exit_code":{},"wall_time":{},"user_time":null,"system_time":null,"max_rss":{},"cache":"{}"}}"#,
+                tool, tool, output, code, wall, rss, cache
+            )
+        };
+        let lines = [
+            record("gcc", "a.o", 0, 1.0, 100, "miss"),
+            record("gcc", "b.o", 1, 3.0, 50, "hit"),
+            record("ld", "a", 0, 1.5, 300, "bypass"),
+            String::new(),
+            record("gcc", "a.o", 0, 0.5, 120, "hit"),
+        ];
+        fs::write(&log, lines.join("\n")).unwrap();
+        let files = vec![log.to_string_lossy().into_owned()];
+        let keys = |text: &str| -> Vec<String> {
+            text.lines()
+                .skip(1)
+                .take_while(|line| !line.is_empty())
+                .map(|line| line.split_whitespace().last().unwrap().to_string())
+                .collect()
+        };
+
+        let text = report(&files, "output", "wall", 20).unwrap();
+        assert_eq!(keys(&text), vec!["b.o", "a", "a.o"]);
+        let a = text.lines().find(|line| line.ends_with(" a.o")).unwrap();
+        let fields: Vec<&str> = a.split_whitespace().collect();
+        assert_eq!(fields[..4], ["2", "0", "1.500", "1.000"]);
+        assert_eq!(fields[6], "120");
+        assert!(text.ends_with("\ncache: 2 hits, 1 misses, 1 bypassed (66.7% hit rate)\n"));
+
+        let text = report(&files, "tool", "rss", 1).unwrap();
+        assert_eq!(keys(&text), vec!["ld"]);
+        let text = report(&files, "tool", "count", 20).unwrap();
+        assert_eq!(keys(&text), vec!["gcc", "ld"]);
+        assert!(text.lines().nth(1).unwrap().starts_with("     3      1"));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_report_options() {
+        let run = |args: &[&str]| {
+            report_command(args.iter().map(|s| s.to_string()))
+                .unwrap_err()
+                .to_string()
+        };
+        assert!(run(&["--sort", "name", "a.json"]).contains("unknown sort column 'name'"));
+        assert!(run(&["--group-by", "dir", "a.json"]).contains("unknown group 'dir'"));
+        assert!(run(&["a.json", "--group-by"]).contains("missing value for --group-by"));
+        assert!(run(&["a.json", "--sort"]).contains("missing value for --sort"));
+        assert!(run(&["a.json", "--top"]).contains("missing value for --top"));
+        assert!(run(&["--top", "many", "a.json"]).contains("invalid --top 'many'"));
+    }
+}
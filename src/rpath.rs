@@ -0,0 +1,331 @@
+use anyhow::Result;
+use std::collections::HashMap;
+use std::env;
+use std::path::{Component, Path, PathBuf};
+
+use crate::argument::{self, ToolKind};
+use crate::{link_spelling, Configuration, ResponseFile};
+
+// 编译器中连续的 -Wl, 和 -Xlinker 参数作为一组链接器参数处理, 因为 libtool 会生成 -Wl,-rpath -Wl,/dir 这种写法
+fn edit_linker_words(
+    kind: ToolKind,
+    arguments: &mut Vec<String>,
+    response_map: &mut HashMap<String, ResponseFile>,
+    edit: &mut dyn FnMut(&mut Vec<String>) -> bool,
+) -> bool {
+    let mut changed = false;
+    for arg in arguments.iter() {
+        if let Some(path) = arg.strip_prefix("@") {
+            if let Some(res) = response_map.get_mut(path) {
+                res.changed |= edit_linker_words(kind, &mut res.values, &mut HashMap::new(), edit);
+            }
+        }
+    }
+    if kind == ToolKind::Linker {
+        return edit(arguments) || changed;
+    }
+
+    let mut result = vec![];
+    let mut run: Vec<String> = vec![];
+    let mut words: Vec<String> = vec![];
+    let mut flush = |run: &mut Vec<String>, words: &mut Vec<String>, result: &mut Vec<String>| {
+        if edit(words) {
+            changed = true;
+            // 包含逗号的参数只能使用 -Xlinker 传递
+            for (comma, group) in words
+                .chunk_by(|a, b| a.contains(',') == b.contains(','))
+                .map(|g| (g[0].contains(','), g))
+            {
+                if comma {
+                    for word in group {
+                        result.extend(["-Xlinker".to_string(), word.clone()]);
+                    }
+                } else {
+                    result.push(format!("-Wl,{}", group.join(",")));
+                }
+            }
+        } else {
+            result.append(run);
+        }
+        run.clear();
+        words.clear();
+    };
+    for arg in argument::parse(kind, arguments) {
+        let tokens = arg.tokens(arguments);
+        if arg.is("-Wl,") {
+            words.extend(
+                arg.value
+                    .unwrap_or_default()
+                    .split(',')
+                    .map(|w| w.to_string()),
+            );
+            run.extend(tokens.iter().cloned());
+        } else if arg.is("-Xlinker") {
+            words.push(arg.value.unwrap_or_default());
+            run.extend(tokens.iter().cloned());
+        } else {
+            flush(&mut run, &mut words, &mut result);
+            result.extend(tokens.iter().cloned());
+        }
+    }
+    flush(&mut run, &mut words, &mut result);
+    *arguments = result;
+    changed
+}
+
+// 使用 f 修改每个 rpath 参数的值(冒号分隔的目录列表), 返回空字符串时删除该参数
+fn map_rpath_words(words: &mut Vec<String>, f: &mut dyn FnMut(&str) -> String) -> bool {
+    let mut changed = false;
+    let mut i = 0;
+    while i < words.len() {
+        let word = words[i].as_str();
+        let separate = matches!(word, "-rpath" | "--rpath" | "-R") && i + 1 < words.len();
+        // -R 的参数是文件时表示 --just-symbols
+        let is_rpath = |value: &str| !Path::new(value).is_file();
+        if separate && is_rpath(&words[i + 1]) {
+            let value = f(&words[i + 1]);
+            if value != words[i + 1] {
+                changed = true;
+                if value.is_empty() {
+                    words.drain(i..i + 2);
+                    continue;
+                }
+                words[i + 1] = value;
+            }
+            i += 2;
+            continue;
+        }
+        let joined = ["-rpath=", "--rpath=", "-R"]
+            .iter()
+            .find_map(|p| word.strip_prefix(p).map(|v| (p, v)))
+            .filter(|(_, v)| !v.is_empty() && is_rpath(v));
+        if let Some((prefix, old)) = joined {
+            let value = f(old);
+            if value != old {
+                changed = true;
+                if value.is_empty() {
+                    words.remove(i);
+                    continue;
+                }
+                words[i] = format!("{}{}", prefix, value);
+            }
+        }
+        i += 1;
+    }
+    changed
+}
+
+fn rpath_entries(kind: ToolKind, config: &Configuration) -> Vec<String> {
+    let mut entries = vec![];
+    edit_linker_words(
+        kind,
+        &mut config.expanded_arguments(),
+        &mut HashMap::new(),
+        &mut |words| {
+            map_rpath_words(words, &mut |value| {
+                entries.extend(value.split(':').map(|v| v.to_string()));
+                value.to_string()
+            })
+        },
+    );
+    entries
+}
+
+fn same_dir(a: &str, b: &str) -> bool {
+    a.trim_end_matches('/') == b.trim_end_matches('/')
+}
+
+pub fn rpath_add_feature(dir: String, kind: ToolKind, config: &mut Configuration) -> Result<()> {
+    if rpath_entries(kind, config)
+        .iter()
+        .any(|e| same_dir(e, &dir))
+    {
+        return Ok(());
+    }
+    if kind == ToolKind::Compiler {
+        config.arguments.push(format!("-Wl,-rpath,{}", dir));
+    } else {
+        config.arguments.extend(["-rpath".to_string(), dir]);
+    }
+    Ok(())
+}
+
+pub fn rpath_remove_feature(dir: String, kind: ToolKind, config: &mut Configuration) -> Result<()> {
+    edit_linker_words(
+        kind,
+        &mut config.arguments,
+        &mut config.response_map,
+        &mut |words| {
+            map_rpath_words(words, &mut |value| {
+                value
+                    .split(':')
+                    .filter(|e| !same_dir(e, &dir))
+                    .collect::<Vec<&str>>()
+                    .join(":")
+            })
+        },
+    );
+    Ok(())
+}
+
+// 只按照字面处理 . 和 .., 不解析符号链接
+fn normalize(path: &Path) -> Vec<String> {
+    let mut parts: Vec<String> = vec![];
+    for component in path.components() {
+        match component {
+            Component::ParentDir => {
+                parts.pop();
+            }
+            Component::Normal(part) => parts.push(part.to_string_lossy().into_owned()),
+            _ => {}
+        }
+    }
+    parts
+}
+
+fn origin_relative(output_dir: &Path, dir: &str) -> String {
+    if !dir.starts_with('/') {
+        return dir.to_string();
+    }
+    let from = normalize(output_dir);
+    let to = normalize(Path::new(dir));
+    let common = from
+        .iter()
+        .zip(to.iter())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let mut parts = vec!["$ORIGIN".to_string()];
+    parts.extend(std::iter::repeat_n("..".to_string(), from.len() - common));
+    parts.extend(to[common..].iter().cloned());
+    parts.join("/")
+}
+
+pub fn rpath_origin_relative_feature(
+    _: String,
+    kind: ToolKind,
+    config: &mut Configuration,
+) -> Result<()> {
+    let output = config.output_file().unwrap_or_else(|| "a.out".to_string());
+    let base = if config.work_dir.is_empty() {
+        env::current_dir()?
+    } else {
+        PathBuf::from(&config.work_dir)
+    };
+    let output = base.join(output);
+    let output_dir = output.parent().unwrap_or(Path::new("/")).to_path_buf();
+    edit_linker_words(
+        kind,
+        &mut config.arguments,
+        &mut config.response_map,
+        &mut |words| {
+            map_rpath_words(words, &mut |value| {
+                value
+                    .split(':')
+                    .map(|e| origin_relative(&output_dir, e))
+                    .collect::<Vec<String>>()
+                    .join(":")
+            })
+        },
+    );
+    Ok(())
+}
+
+// 删除已有的 --enable-new-dtags/--disable-new-dtags, 然后在末尾添加指定的写法
+fn new_dtags_feature(enable: bool, kind: ToolKind, config: &mut Configuration) {
+    edit_linker_words(
+        kind,
+        &mut config.arguments,
+        &mut config.response_map,
+        &mut |words| {
+            let len = words.len();
+            words.retain(|w| {
+                !matches!(
+                    w.as_str(),
+                    "--enable-new-dtags"
+                        | "-enable-new-dtags"
+                        | "--disable-new-dtags"
+                        | "-disable-new-dtags"
+                )
+            });
+            words.len() != len
+        },
+    );
+    let option = if enable {
+        "--enable-new-dtags"
+    } else {
+        "--disable-new-dtags"
+    };
+    config.arguments.push(link_spelling(kind, option));
+}
+
+pub fn runpath_feature(_: String, kind: ToolKind, config: &mut Configuration) -> Result<()> {
+    new_dtags_feature(true, kind, config);
+    Ok(())
+}
+
+pub fn rpath_feature(_: String, kind: ToolKind, config: &mut Configuration) -> Result<()> {
+    new_dtags_feature(false, kind, config);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(args: &[&str]) -> Vec<String> {
+        args.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_rpath_features() {
+        let mut config = Configuration::new();
+        config.work_dir = "/build/out".to_owned();
+        config.arguments = strings(&[
+            "-o",
+            "bin/app",
+            "-Wl,-rpath",
+            "-Wl,/opt/a:/build/out/lib",
+            "main.o",
+            "-Wl,--enable-new-dtags,-rpath=/old,-O1",
+            "-Xlinker",
+            "-rpath",
+            "-Xlinker",
+            "/build/x,y",
+        ]);
+        rpath_remove_feature("/old/".to_owned(), ToolKind::Compiler, &mut config).unwrap();
+        rpath_add_feature("/opt/a".to_owned(), ToolKind::Compiler, &mut config).unwrap();
+        rpath_add_feature("/opt/b".to_owned(), ToolKind::Compiler, &mut config).unwrap();
+        rpath_origin_relative_feature(String::new(), ToolKind::Compiler, &mut config).unwrap();
+        rpath_feature(String::new(), ToolKind::Compiler, &mut config).unwrap();
+        assert_eq!(
+            config.arguments,
+            strings(&[
+                "-o",
+                "bin/app",
+                "-Wl,-rpath,$ORIGIN/../../../opt/a:$ORIGIN/../lib",
+                "main.o",
+                "-Wl,-O1,-rpath",
+                "-Xlinker",
+                "$ORIGIN/../../x,y",
+                "-Wl,-rpath,$ORIGIN/../../../opt/b",
+                "-Wl,--disable-new-dtags",
+            ])
+        );
+
+        config.arguments = strings(&["-rpath", "/a", "-R/b", "--rpath=/c:/d", "-o", "out"]);
+        rpath_remove_feature("/c".to_owned(), ToolKind::Linker, &mut config).unwrap();
+        rpath_remove_feature("/b".to_owned(), ToolKind::Linker, &mut config).unwrap();
+        runpath_feature(String::new(), ToolKind::Linker, &mut config).unwrap();
+        assert_eq!(
+            config.arguments,
+            strings(&[
+                "-rpath",
+                "/a",
+                "--rpath=/d",
+                "-o",
+                "out",
+                "--enable-new-dtags"
+            ])
+        );
+    }
+}
@@ -0,0 +1,1072 @@
+use anyhow::{bail, Result};
+use regex::{Regex, RegexBuilder};
+use std::sync::OnceLock;
+
+use crate::argument::ToolKind;
+use crate::output::{Checksum, OutputStep};
+use crate::status::ExitRule;
+use crate::{cache, link, rpath, verify, Configuration};
+
+pub const PREFIX: &str = "-clw-";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Syntax {
+    // -clw-just-print
+    Flag,
+    // -clw-remove=<arg>
+    Value(&'static str),
+    // -clw-all-static[=<a>,<b>,...]
+    OptionalValue(&'static str),
+    // -clw-replace-<before>=<after>
+    Pair(&'static str, &'static str),
+}
+
+impl Syntax {
+    pub fn usage(&self, name: &str) -> String {
+        match self {
+            Syntax::Flag => format!("{}{}", PREFIX, name),
+            Syntax::Value(value) => format!("{}{}=<{}>", PREFIX, name, value),
+            Syntax::OptionalValue(value) => format!("{}{}[=<{}>]", PREFIX, name, value),
+            Syntax::Pair(before, after) => format!("{}{}-<{}>=<{}>", PREFIX, name, before, after),
+        }
+    }
+
+    // 规则名称之后的内容, 不匹配时返回 None
+    pub fn rest<'a>(&self, name: &str, key: &'a str) -> Option<&'a str> {
+        let rest = key.strip_prefix(name)?;
+        match self {
+            Syntax::Flag => rest.is_empty().then_some(rest),
+            Syntax::Value(_) => rest.strip_prefix('='),
+            Syntax::OptionalValue(_) => {
+                if rest.is_empty() {
+                    Some(rest)
+                } else {
+                    rest.strip_prefix('=')
+                }
+            }
+            Syntax::Pair(_, _) => rest.strip_prefix('-'),
+        }
+    }
+}
+
+pub enum CommandType {
+    Flag,
+    Command(Box<dyn Rule>),
+    // 需要一个参数
+    Option,
+    Ignore,
+    // 规则的值无效, 包含错误信息
+    Invalid(String),
+}
+
+// -clw- 规则, 注册表中的规则负责解析, 解析得到的规则在所有参数收集完成后依次应用
+pub trait Rule: Send + Sync {
+    // 不包括 -clw- 前缀
+    fn name(&self) -> &str;
+
+    fn syntax(&self) -> Syntax;
+
+    fn help(&self) -> &str;
+
+    // value 为规则名称之后的内容, 返回 None 表示不匹配, 规则会被转发给命令,
+    // 返回 CommandType::Invalid 表示值无效, 命令不会被执行
+    fn parse(&self, value: &str, config: &mut Configuration) -> Option<CommandType>;
+
+    fn apply(&self, _config: &mut Configuration) -> Result<()> {
+        Ok(())
+    }
+
+    fn usage(&self) -> String {
+        self.syntax().usage(self.name())
+    }
+
+    fn describe(&self) -> String {
+        format!("{:<40} {}", self.usage(), self.help())
+    }
+}
+
+// -<before>=<after> 两部分都不能为空
+fn split_pair(value: &str) -> Option<(String, String)> {
+    match value.split_once('=') {
+        Some((first, second)) if !first.is_empty() && !second.is_empty() => {
+            Some((first.to_string(), second.to_string()))
+        }
+        _ => None,
+    }
+}
+
+// 大小可以带 K/M/G 后缀, 单位为 1024
+fn size(value: &str) -> Result<u64, String> {
+    cache::parse_size(value).ok_or_else(|| {
+        format!(
+            "invalid size '{}', expected <number>[B|K|M|G], e.g. 4096, 500K, 64M or 5G",
+            value
+        )
+    })
+}
+
+// 匹配命令输出的正则表达式, ^ 和 $ 匹配每一行
+fn output_pattern(value: &str) -> Result<Regex, String> {
+    RegexBuilder::new(value)
+        .multi_line(true)
+        .build()
+        .map_err(|e| format!("invalid regex '{}': {}", value, e))
+}
+
+// 只修改配置的规则
+pub struct Setting {
+    name: &'static str,
+    syntax: Syntax,
+    help: &'static str,
+    set: fn(&mut Configuration, &str) -> Result<(), String>,
+}
+
+impl Setting {
+    pub fn new(
+        name: &'static str,
+        syntax: Syntax,
+        help: &'static str,
+        set: fn(&mut Configuration, &str) -> Result<(), String>,
+    ) -> Setting {
+        Setting {
+            name,
+            syntax,
+            help,
+            set,
+        }
+    }
+}
+
+impl Rule for Setting {
+    fn name(&self) -> &str {
+        self.name
+    }
+
+    fn syntax(&self) -> Syntax {
+        self.syntax
+    }
+
+    fn help(&self) -> &str {
+        self.help
+    }
+
+    fn parse(&self, value: &str, config: &mut Configuration) -> Option<CommandType> {
+        // 无效的值直接报错, 不转发给命令
+        if let Err(message) = (self.set)(config, value) {
+            return Some(CommandType::Invalid(format!(
+                "invalid value for {}: {}",
+                self.usage(),
+                message
+            )));
+        }
+        match self.syntax {
+            Syntax::Flag => Some(CommandType::Flag),
+            _ => Some(CommandType::Option),
+        }
+    }
+}
+
+// 使用特性函数修改参数的规则
+pub struct Feature {
+    name: &'static str,
+    syntax: Syntax,
+    help: &'static str,
+    feature: fn(String, &mut Configuration) -> Result<()>,
+    value: String,
+}
+
+impl Feature {
+    pub fn new(
+        name: &'static str,
+        syntax: Syntax,
+        help: &'static str,
+        feature: fn(String, &mut Configuration) -> Result<()>,
+    ) -> Feature {
+        Feature {
+            name,
+            syntax,
+            help,
+            feature,
+            value: String::new(),
+        }
+    }
+}
+
+impl Rule for Feature {
+    fn name(&self) -> &str {
+        self.name
+    }
+
+    fn syntax(&self) -> Syntax {
+        self.syntax
+    }
+
+    fn help(&self) -> &str {
+        self.help
+    }
+
+    fn parse(&self, value: &str, _: &mut Configuration) -> Option<CommandType> {
+        Some(CommandType::Command(Box::new(Feature {
+            value: value.to_string(),
+            ..*self
+        })))
+    }
+
+    fn apply(&self, config: &mut Configuration) -> Result<()> {
+        (self.feature)(self.value.clone(), config)
+    }
+}
+
+// 修改链接参数的规则, kind 决定链接器参数是否需要 -Wl, 传递
+pub struct LinkFeature {
+    name: &'static str,
+    syntax: Syntax,
+    help: &'static str,
+    kind: ToolKind,
+    feature: fn(String, ToolKind, &mut Configuration) -> Result<()>,
+    value: String,
+}
+
+impl LinkFeature {
+    // 链接器使用的写法和编译器使用的 -compiler 写法
+    pub fn new(
+        names: [&'static str; 2],
+        syntax: Syntax,
+        help: [&'static str; 2],
+        feature: fn(String, ToolKind, &mut Configuration) -> Result<()>,
+    ) -> [LinkFeature; 2] {
+        [ToolKind::Linker, ToolKind::Compiler].map(|kind| {
+            let i = (kind == ToolKind::Compiler) as usize;
+            LinkFeature {
+                name: names[i],
+                syntax,
+                help: help[i],
+                kind,
+                feature,
+                value: String::new(),
+            }
+        })
+    }
+}
+
+impl Rule for LinkFeature {
+    fn name(&self) -> &str {
+        self.name
+    }
+
+    fn syntax(&self) -> Syntax {
+        self.syntax
+    }
+
+    fn help(&self) -> &str {
+        self.help
+    }
+
+    fn parse(&self, value: &str, _: &mut Configuration) -> Option<CommandType> {
+        Some(CommandType::Command(Box::new(LinkFeature {
+            value: value.to_string(),
+            ..*self
+        })))
+    }
+
+    fn apply(&self, config: &mut Configuration) -> Result<()> {
+        (self.feature)(self.value.clone(), self.kind, config)
+    }
+}
+
+pub struct Replace {
+    before: String,
+    after: String,
+}
+
+impl Rule for Replace {
+    fn name(&self) -> &str {
+        "replace"
+    }
+
+    fn syntax(&self) -> Syntax {
+        Syntax::Pair("before", "after")
+    }
+
+    fn help(&self) -> &str {
+        "Replace all <before> arguments with <after>"
+    }
+
+    fn parse(&self, value: &str, _: &mut Configuration) -> Option<CommandType> {
+        let (before, after) = split_pair(value)?;
+        Some(CommandType::Command(Box::new(Replace { before, after })))
+    }
+
+    fn apply(&self, config: &mut Configuration) -> Result<()> {
+        crate::replace_argument_feature(self.before.clone(), Some(self.after.clone()), config)
+    }
+}
+
+// -clw-move-front=<arg> 或者以前后参数为条件的 -clw-move-front-before-<before>=<arg>
+pub struct Move {
+    name: &'static str,
+    anchor: Option<&'static str>,
+    help: &'static str,
+    feature: fn(String, Option<String>, &mut Configuration) -> Result<()>,
+    value: String,
+    anchor_value: Option<String>,
+}
+
+impl Move {
+    fn new(
+        name: &'static str,
+        anchor: Option<&'static str>,
+        help: &'static str,
+        feature: fn(String, Option<String>, &mut Configuration) -> Result<()>,
+    ) -> Move {
+        Move {
+            name,
+            anchor,
+            help,
+            feature,
+            value: String::new(),
+            anchor_value: None,
+        }
+    }
+}
+
+impl Rule for Move {
+    fn name(&self) -> &str {
+        self.name
+    }
+
+    fn syntax(&self) -> Syntax {
+        match self.anchor {
+            Some(anchor) => Syntax::Pair(anchor, "arg"),
+            None => Syntax::Value("arg"),
+        }
+    }
+
+    fn help(&self) -> &str {
+        self.help
+    }
+
+    fn parse(&self, value: &str, _: &mut Configuration) -> Option<CommandType> {
+        let (value, anchor_value) = match self.anchor {
+            Some(_) => {
+                let (anchor, value) = split_pair(value)?;
+                (value, Some(anchor))
+            }
+            None => (value.to_string(), None),
+        };
+        Some(CommandType::Command(Box::new(Move {
+            value,
+            anchor_value,
+            ..*self
+        })))
+    }
+
+    fn apply(&self, config: &mut Configuration) -> Result<()> {
+        (self.feature)(self.value.clone(), self.anchor_value.clone(), config)
+    }
+}
+
+#[derive(Default)]
+pub struct Registry {
+    rules: Vec<Box<dyn Rule>>,
+}
+
+impl Registry {
+    pub fn new() -> Registry {
+        Registry::default()
+    }
+
+    // 与已有规则同名时后注册的规则优先
+    pub fn register(&mut self, rule: Box<dyn Rule>) {
+        self.rules.push(rule);
+    }
+
+    pub fn rules(&self) -> impl Iterator<Item = &dyn Rule> {
+        self.rules.iter().map(|rule| rule.as_ref())
+    }
+
+    // 查找 key 对应的规则和名称之后的内容, 较长的名称优先, 如 rpath-add 和 rpath
+    pub fn find<'a>(&self, key: &'a str) -> Option<(&dyn Rule, &'a str)> {
+        self.rules()
+            .filter_map(|rule| {
+                rule.syntax()
+                    .rest(rule.name(), key)
+                    .map(|rest| (rule, rest))
+            })
+            .max_by_key(|(rule, _)| rule.name().len())
+    }
+
+    // key 不包括 -clw- 前缀
+    pub fn parse(&self, config: &mut Configuration, key: &str) -> CommandType {
+        self.find(key)
+            .and_then(|(rule, rest)| rule.parse(rest, config))
+            .unwrap_or(CommandType::Ignore)
+    }
+
+    // 对 argv 应用 -clw- 规则并返回新的 argv, argv[0] 为被包装的命令.
+    // 规则可以省略 -clw- 前缀, 只修改参数, 不展开 ResponseFile
+    pub fn rewrite<S: AsRef<str>>(&self, argv: &[String], rules: &[S]) -> Result<Vec<String>> {
+        let Some((command, arguments)) = argv.split_first() else {
+            bail!("argv is empty");
+        };
+        let mut config = Configuration::new();
+        config.command = command.clone();
+        config.arguments = arguments.to_vec();
+
+        let mut commands = vec![];
+        for rule in rules {
+            let rule = rule.as_ref();
+            match self.parse(&mut config, rule.strip_prefix(PREFIX).unwrap_or(rule)) {
+                CommandType::Command(c) => commands.push(c),
+                CommandType::Ignore => bail!("unknown rule {}", rule),
+                CommandType::Invalid(message) => bail!("{}", message),
+                _ => {}
+            }
+        }
+        for c in commands {
+            c.apply(&mut config)?;
+        }
+
+        let mut result = vec![config.command.clone()];
+        result.append(&mut config.arguments);
+        Ok(result)
+    }
+
+    pub fn builtin() -> Registry {
+        let mut registry = Registry::new();
+        let settings = [
+            Setting::new(
+                "just-print",
+                Syntax::Flag,
+                "Only print the final command without executing it",
+                |config, _| {
+                    config.just_print = true;
+                    Ok(())
+                },
+            ),
+            Setting::new(
+                "explain",
+                Syntax::Flag,
+                "Print how every rule changes the arguments without executing the command",
+                |config, _| {
+                    config.explain = true;
+                    Ok(())
+                },
+            ),
+            Setting::new(
+                "before-print",
+                Syntax::Flag,
+                "Print the final command before executing it",
+                |config, _| {
+                    config.before_print = true;
+                    Ok(())
+                },
+            ),
+            Setting::new(
+                "log-file",
+                Syntax::Value("log file"),
+                "Append the internal log to the file",
+                |config, value| {
+                    config.log_file = value.to_string();
+                    Ok(())
+                },
+            ),
+            Setting::new(
+                "json-log",
+                Syntax::Value("log file"),
+                "Append one JSON record per invocation to the file",
+                |config, value| {
+                    config.json_log = value.to_string();
+                    Ok(())
+                },
+            ),
+            Setting::new(
+                "reproducible",
+                Syntax::Value("from>=<to"),
+                "Map the <from> path prefix to <to> for reproducible builds",
+                |config, value| match value.split_once('=') {
+                    Some((from, to)) if !from.is_empty() => {
+                        config.prefix_maps.push((from.to_string(), to.to_string()));
+                        Ok(())
+                    }
+                    _ => Err(format!("expected <from>=<to>, found '{}'", value)),
+                },
+            ),
+            Setting::new(
+                "verify-needed",
+                Syntax::Value("soname"),
+                "Fail if the linked output has no DT_NEEDED entry for <soname>",
+                |config, value| {
+                    config
+                        .verifications
+                        .push(verify::Verification::Needed(value.to_string()));
+                    Ok(())
+                },
+            ),
+            Setting::new(
+                "verify-no-needed",
+                Syntax::Value("soname"),
+                "Fail if the linked output has a DT_NEEDED entry for <soname>",
+                |config, value| {
+                    config
+                        .verifications
+                        .push(verify::Verification::NoNeeded(value.to_string()));
+                    Ok(())
+                },
+            ),
+            Setting::new(
+                "verify-no-undefined",
+                Syntax::Flag,
+                "Fail if the linked output has unresolved undefined symbols",
+                |config, _| {
+                    config.verifications.push(verify::Verification::NoUndefined);
+                    Ok(())
+                },
+            ),
+            Setting::new(
+                "verify-interpreter",
+                Syntax::OptionalValue("path"),
+                "Fail if the program interpreter of the linked output is not <path>",
+                |config, value| {
+                    config
+                        .verifications
+                        .push(verify::Verification::Interpreter(value.to_string()));
+                    Ok(())
+                },
+            ),
+            Setting::new(
+                "atomic-output",
+                Syntax::Flag,
+                "Write the output to a temporary file and rename it only if the command succeeds",
+                |config, _| {
+                    config.atomic_output = true;
+                    Ok(())
+                },
+            ),
+            Setting::new(
+                "depfile-rewrite",
+                Syntax::Value("from>=<to"),
+                "Replace the <from> path prefix with <to> in the depfile after the command succeeds",
+                |config, value| match value.split_once('=') {
+                    Some((from, to)) if !from.is_empty() => {
+                        config
+                            .depfile_rewrites
+                            .push((from.to_string(), to.to_string()));
+                        Ok(())
+                    }
+                    _ => Err(format!("expected <from>=<to>, found '{}'", value)),
+                },
+            ),
+            Setting::new(
+                "depfile-relative",
+                Syntax::Flag,
+                "Make the absolute paths in the depfile relative to the working directory",
+                |config, _| {
+                    config.depfile_relative = true;
+                    Ok(())
+                },
+            ),
+            Setting::new(
+                "output-strip",
+                Syntax::Value("tool"),
+                "Run <tool> on the output after the command succeeds, e.g. strip",
+                |config, value| {
+                    config
+                        .output_steps
+                        .push(OutputStep::Strip(value.to_string()));
+                    Ok(())
+                },
+            ),
+            Setting::new(
+                "output-checksum",
+                Syntax::Value("algo"),
+                "Write the sha224/sha256/sha384/sha512 checksum of the output to <output>.<algo>",
+                |config, value| match Checksum::parse(value) {
+                    Some(checksum) => {
+                        config.output_steps.push(OutputStep::Checksum(checksum));
+                        Ok(())
+                    }
+                    None => Err(format!(
+                        "unknown algorithm '{}', expected sha224, sha256, sha384 or sha512",
+                        value
+                    )),
+                },
+            ),
+            Setting::new(
+                "output-copy",
+                Syntax::Value("dir"),
+                "Copy the output and its checksum files to <dir>",
+                |config, value| {
+                    config
+                        .output_steps
+                        .push(OutputStep::Copy(value.to_string()));
+                    Ok(())
+                },
+            ),
+            Setting::new(
+                "cache-dir",
+                Syntax::Value("dir"),
+                "Cache the object files of compilations in <dir>",
+                |config, value| {
+                    config.cache_dir = value.to_string();
+                    Ok(())
+                },
+            ),
+            Setting::new(
+                "cache-max-size",
+                Syntax::Value("size"),
+                "Maximum cache size with K/M/G suffixes, default 5G",
+                |config, value| {
+                    config.cache_max_size = size(value)?;
+                    Ok(())
+                },
+            ),
+            Setting::new(
+                "max-command-line",
+                Syntax::Value("size"),
+                "Pass the arguments with an @file response file when the command line is longer than <size>",
+                |config, value| {
+                    config.max_command_line = size(value)?;
+                    Ok(())
+                },
+            ),
+            Setting::new(
+                "command",
+                Syntax::Value("command"),
+                "Execute <command> instead of the current program",
+                |config, value| {
+                    config.command = value.to_string();
+                    Ok(())
+                },
+            ),
+            Setting::new(
+                "work-dir",
+                Syntax::Value("working directory"),
+                "Change the working directory of the command",
+                |config, value| {
+                    config.work_dir = value.to_string();
+                    Ok(())
+                },
+            ),
+            Setting::new(
+                "redirect-stdout",
+                Syntax::Value("file path"),
+                "Redirect stdout to the file",
+                |config, value| {
+                    config.redirect_stdout = value.to_string();
+                    Ok(())
+                },
+            ),
+            Setting::new(
+                "filter-stderr",
+                Syntax::Value("regex"),
+                "Drop the stderr warnings whose diagnostic line or include context matches <regex>",
+                |config, value| match Regex::new(value) {
+                    Ok(pattern) => {
+                        config.stderr_filters.push(pattern);
+                        Ok(())
+                    }
+                    Err(e) => Err(format!("invalid regex '{}': {}", value, e)),
+                },
+            ),
+            Setting::new(
+                "rewrite-stderr",
+                Syntax::Value("regex>=<replacement"),
+                "Replace <regex> in every line of stderr with <replacement>",
+                |config, value| match value.split_once('=') {
+                    Some((pattern, replacement)) => match Regex::new(pattern) {
+                        Ok(pattern) => {
+                            config
+                                .stderr_rewrites
+                                .push((pattern, replacement.to_string()));
+                            Ok(())
+                        }
+                        Err(e) => Err(format!("invalid regex '{}': {}", pattern, e)),
+                    },
+                    None => Err(format!("expected <regex>=<replacement>, found '{}'", value)),
+                },
+            ),
+            Setting::new(
+                "fail-if-output",
+                Syntax::Value("regex"),
+                "Exit with 1 when the command succeeds but its output matches <regex>",
+                |config, value| {
+                    let pattern = output_pattern(value)?;
+                    config.exit_rules.push(ExitRule::FailIfOutput(pattern));
+                    Ok(())
+                },
+            ),
+            Setting::new(
+                "succeed-if-output",
+                Syntax::Value("regex"),
+                "Exit with 0 when the command fails but its output matches <regex>",
+                |config, value| {
+                    let pattern = output_pattern(value)?;
+                    config.exit_rules.push(ExitRule::SucceedIfOutput(pattern));
+                    Ok(())
+                },
+            ),
+            Setting::new(
+                "pre-hook",
+                Syntax::Value("cmd"),
+                "Run the shell command before executing the command",
+                |config, value| {
+                    config.pre_hooks.push(value.to_string());
+                    Ok(())
+                },
+            ),
+            Setting::new(
+                "post-hook",
+                Syntax::Value("cmd"),
+                "Run the shell command after the command succeeds",
+                |config, value| {
+                    config.post_hooks.push(value.to_string());
+                    Ok(())
+                },
+            ),
+            Setting::new(
+                "hook-ignore-failure",
+                Syntax::Flag,
+                "Only warn when a hook fails instead of failing the build",
+                |config, _| {
+                    config.hook_ignore_failure = true;
+                    Ok(())
+                },
+            ),
+            Setting::new(
+                "post-hook-always",
+                Syntax::Flag,
+                "Run the post hooks even if the command fails",
+                |config, _| {
+                    config.post_hook_always = true;
+                    Ok(())
+                },
+            ),
+            Setting::new(
+                "script-allow-write",
+                Syntax::Flag,
+                "Allow -clw-script scripts to write files",
+                |config, _| {
+                    config.script_allow_write = true;
+                    Ok(())
+                },
+            ),
+            Setting::new(
+                "redirect-stderr",
+                Syntax::Value("file path"),
+                "Redirect stderr to the file",
+                |config, value| {
+                    config.redirect_stderr = value.to_string();
+                    Ok(())
+                },
+            ),
+        ];
+        for setting in settings {
+            registry.register(Box::new(setting));
+        }
+
+        registry.register(Box::new(Feature::new(
+            "remove",
+            Syntax::Value("arg"),
+            "Remove all <arg> arguments",
+            crate::remove_argument_feature,
+        )));
+        registry.register(Box::new(Replace {
+            before: String::new(),
+            after: String::new(),
+        }));
+        registry.register(Box::new(Feature::new(
+            "resolve-static",
+            Syntax::Value("lib"),
+            "Replace -l<lib> with the path of lib<lib>.a",
+            link::resolve_static_feature,
+        )));
+        let link_features = [
+            LinkFeature::new(
+                ["static-link", "static-link-compiler"],
+                Syntax::Value("arg"),
+                [
+                    "Link <arg> statically with -Bstatic/-Bdynamic",
+                    "Link <arg> statically with -Wl,-Bstatic/-Wl,-Bdynamic",
+                ],
+                crate::static_link_feature,
+            ),
+            LinkFeature::new(
+                ["dynamic-link", "dynamic-link-compiler"],
+                Syntax::Value("arg"),
+                [
+                    "Link <arg> dynamically with -Bdynamic/-Bstatic",
+                    "Link <arg> dynamically with -Wl,-Bdynamic/-Wl,-Bstatic",
+                ],
+                crate::dynamic_link_feature,
+            ),
+            LinkFeature::new(
+                ["all-static", "all-static-compiler"],
+                Syntax::OptionalValue("a>,<b>,..."),
+                [
+                    "Apply -clw-static-link to every -l library not in the allowlist",
+                    "Apply -clw-static-link-compiler to every -l library not in the allowlist",
+                ],
+                crate::all_static_feature,
+            ),
+            LinkFeature::new(
+                ["all-dynamic", "all-dynamic-compiler"],
+                Syntax::OptionalValue("a>,<b>,..."),
+                [
+                    "Apply -clw-dynamic-link to every -l library not in the allowlist",
+                    "Apply -clw-dynamic-link-compiler to every -l library not in the allowlist",
+                ],
+                crate::all_dynamic_feature,
+            ),
+            LinkFeature::new(
+                ["whole-archive", "whole-archive-compiler"],
+                Syntax::Value("arg"),
+                [
+                    "Wrap <arg> with --whole-archive/--no-whole-archive",
+                    "Wrap <arg> with -Wl,--whole-archive/-Wl,--no-whole-archive",
+                ],
+                crate::whole_archive_feature,
+            ),
+            LinkFeature::new(
+                ["link-group", "link-group-compiler"],
+                Syntax::Value("a>,<b>,..."),
+                [
+                    "Move the libraries into one --start-group/--end-group",
+                    "Move the libraries into one -Wl,--start-group/-Wl,--end-group",
+                ],
+                crate::link_group_feature,
+            ),
+            LinkFeature::new(
+                ["fix-link-order", "fix-link-order-compiler"],
+                Syntax::Flag,
+                [
+                    "Reorder the archives by their symbol dependencies",
+                    "Reorder the archives by their symbol dependencies, using -Wl, for groups",
+                ],
+                link::fix_link_order_feature,
+            ),
+            LinkFeature::new(
+                ["rpath-add", "rpath-add-compiler"],
+                Syntax::Value("dir"),
+                [
+                    "Append -rpath <dir> unless it is already an rpath",
+                    "Append -Wl,-rpath,<dir> unless it is already an rpath",
+                ],
+                rpath::rpath_add_feature,
+            ),
+            LinkFeature::new(
+                ["rpath-remove", "rpath-remove-compiler"],
+                Syntax::Value("dir"),
+                [
+                    "Remove <dir> from all the rpaths",
+                    "Remove <dir> from all the rpaths passed by -Wl, or -Xlinker",
+                ],
+                rpath::rpath_remove_feature,
+            ),
+            LinkFeature::new(
+                ["rpath-origin-relative", "rpath-origin-relative-compiler"],
+                Syntax::Flag,
+                [
+                    "Rewrite absolute rpaths relative to $ORIGIN",
+                    "Rewrite absolute rpaths passed by -Wl, or -Xlinker relative to $ORIGIN",
+                ],
+                rpath::rpath_origin_relative_feature,
+            ),
+            LinkFeature::new(
+                ["runpath", "runpath-compiler"],
+                Syntax::Flag,
+                [
+                    "Emit DT_RUNPATH with --enable-new-dtags",
+                    "Emit DT_RUNPATH with -Wl,--enable-new-dtags",
+                ],
+                rpath::runpath_feature,
+            ),
+            LinkFeature::new(
+                ["rpath", "rpath-compiler"],
+                Syntax::Flag,
+                [
+                    "Emit DT_RPATH with --disable-new-dtags",
+                    "Emit DT_RPATH with -Wl,--disable-new-dtags",
+                ],
+                rpath::rpath_feature,
+            ),
+        ];
+        for feature in link_features.into_iter().flatten() {
+            registry.register(Box::new(feature));
+        }
+
+        #[cfg(feature = "script")]
+        registry.register(Box::new(Feature::new(
+            "script",
+            Syntax::Value("file"),
+            "Run the Rhai script to modify the command, arguments, response files, env and cwd",
+            crate::script::script_feature,
+        )));
+
+        let moves = [
+            Move::new(
+                "move-front",
+                None,
+                "Move all <arg> arguments to the front",
+                crate::move_to_front_for_before_feature,
+            ),
+            Move::new(
+                "move-front-before",
+                Some("before"),
+                "Move the <arg> arguments preceded by <before> to the front",
+                crate::move_to_front_for_before_feature,
+            ),
+            Move::new(
+                "move-front-after",
+                Some("after"),
+                "Move the <arg> arguments followed by <after> to the front",
+                crate::move_to_front_for_after_feature,
+            ),
+            Move::new(
+                "move-back",
+                None,
+                "Move all <arg> arguments to the back",
+                crate::move_to_back_for_before_feature,
+            ),
+            Move::new(
+                "move-back-before",
+                Some("before"),
+                "Move the <arg> arguments preceded by <before> to the back",
+                crate::move_to_back_for_before_feature,
+            ),
+            Move::new(
+                "move-back-after",
+                Some("after"),
+                "Move the <arg> arguments followed by <after> to the back",
+                crate::move_to_back_for_after_feature,
+            ),
+        ];
+        for rule in moves {
+            registry.register(Box::new(rule));
+        }
+        registry
+    }
+}
+
+// 内置规则的注册表
+pub fn builtin() -> &'static Registry {
+    static BUILTIN: OnceLock<Registry> = OnceLock::new();
+    BUILTIN.get_or_init(Registry::builtin)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Append(String);
+
+    impl Rule for Append {
+        fn name(&self) -> &str {
+            "append"
+        }
+
+        fn syntax(&self) -> Syntax {
+            Syntax::Value("arg")
+        }
+
+        fn help(&self) -> &str {
+            "Append <arg>"
+        }
+
+        fn parse(&self, value: &str, _: &mut Configuration) -> Option<CommandType> {
+            Some(CommandType::Command(Box::new(Append(value.to_string()))))
+        }
+
+        fn apply(&self, config: &mut Configuration) -> Result<()> {
+            config.arguments.push(self.0.clone());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_registry() {
+        let argv: Vec<String> = ["gcc", "a.o", "-lm"].map(String::from).to_vec();
+        let mut registry = Registry::builtin();
+        assert!(registry.rewrite(&argv, &["append=-lc"]).is_err());
+        registry.register(Box::new(Append(String::new())));
+        assert_eq!(
+            registry
+                .rewrite(&argv, &["-clw-append=-lc", "move-back=-lm"])
+                .unwrap(),
+            vec!["gcc", "a.o", "-lc", "-lm"]
+        );
+
+        let (rule, rest) = registry.find("rpath-add=/opt").unwrap();
+        assert_eq!((rule.name(), rest), ("rpath-add", "/opt"));
+        assert!(registry.find("static-link-compilers=-lc").is_none());
+        assert!(matches!(
+            registry.parse(&mut Configuration::new(), "replace--lm="),
+            CommandType::Ignore
+        ));
+    }
+
+    fn invalid(key: &str) -> String {
+        match builtin().parse(&mut Configuration::new(), key) {
+            CommandType::Invalid(message) => message,
+            _ => panic!("{} is valid", key),
+        }
+    }
+
+    #[test]
+    fn test_invalid_setting() {
+        assert!(invalid("max-command-line=lots").contains("'lots'"));
+        assert!(invalid("reproducible=/src").contains("<from>=<to>"));
+        assert!(invalid("depfile-rewrite==/b").contains("'=/b'"));
+        let argv: Vec<String> = ["gcc", "-c", "a.c"].map(String::from).to_vec();
+        let error = builtin()
+            .rewrite(&argv, &["max-command-line=1X"])
+            .unwrap_err()
+            .to_string();
+        assert!(error.starts_with("invalid value for -clw-max-command-line=<size>"));
+    }
+
+    #[test]
+    fn test_invalid_size() {
+        let message = invalid("cache-max-size=5T");
+        assert!(message.starts_with("invalid value for -clw-cache-max-size=<size>"));
+        assert!(message.contains("'5T'") && message.contains("<number>[B|K|M|G]"));
+        let mut config = Configuration::new();
+        builtin().parse(&mut config, "cache-max-size=64M");
+        assert_eq!(config.cache_max_size, 64 * 1024 * 1024);
+    }
+
+    #[test]
+    fn test_invalid_checksum() {
+        let message = invalid("output-checksum=md5");
+        assert!(message.contains("unknown algorithm 'md5'"));
+        assert!(message.contains("sha224, sha256, sha384 or sha512"));
+        let mut config = Configuration::new();
+        builtin().parse(&mut config, "output-checksum=SHA256");
+        assert_eq!(config.output_steps.len(), 1);
+    }
+
+    #[test]
+    fn test_invalid_stderr_pattern() {
+        let message = invalid("filter-stderr=unused(");
+        assert!(message.starts_with("invalid value for -clw-filter-stderr=<regex>"));
+        assert!(message.contains("invalid regex 'unused('"));
+        assert!(invalid("rewrite-stderr=[a=b").contains("invalid regex '[a'"));
+        assert!(invalid("rewrite-stderr=warning").contains("<regex>=<replacement>"));
+        assert!(matches!(
+            builtin().parse(&mut Configuration::new(), "filter-stderr=unused"),
+            CommandType::Option
+        ));
+    }
+
+    #[test]
+    fn test_invalid_output_pattern() {
+        assert!(invalid("fail-if-output=warning: (").contains("invalid regex 'warning: ('"));
+        assert!(invalid("succeed-if-output=*ok").contains("invalid regex '*ok'"));
+        let mut config = Configuration::new();
+        assert!(matches!(
+            builtin().parse(&mut config, "fail-if-output=^warning"),
+            CommandType::Option
+        ));
+        assert_eq!(config.exit_rules.len(), 1);
+    }
+}
@@ -0,0 +1,224 @@
+use anyhow::{anyhow, Result};
+use rhai::{Array, Dynamic, Engine, EvalAltResult, Map, Scope};
+use simplelog::{debug, info};
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+use crate::Configuration;
+
+// 限制脚本的运算次数, 避免死循环卡住构建
+const MAX_OPERATIONS: u64 = 10_000_000;
+
+fn to_array(values: &[String]) -> Array {
+    values.iter().cloned().map(Dynamic::from).collect()
+}
+
+fn from_array(name: &str, value: Dynamic) -> Result<Vec<String>> {
+    let type_name = value.type_name();
+    let array = value
+        .try_cast::<Array>()
+        .ok_or_else(|| anyhow!("{} must be an array, found {}", name, type_name))?;
+    array
+        .into_iter()
+        .map(|v| {
+            v.into_string()
+                .map_err(|t| anyhow!("{} must only contain strings, found {}", name, t))
+        })
+        .collect()
+}
+
+fn from_string(name: &str, value: Dynamic) -> Result<String> {
+    value
+        .into_string()
+        .map_err(|t| anyhow!("{} must be a string, found {}", name, t))
+}
+
+// 错误信息使用 <file>:<line>:<column> 的格式
+fn script_error(path: &str, mut error: Box<EvalAltResult>) -> anyhow::Error {
+    let position = error.take_position();
+    match (position.line(), position.position()) {
+        (Some(line), Some(column)) => anyhow!("script {}:{}:{}: {}", path, line, column, error),
+        (Some(line), None) => anyhow!("script {}:{}: {}", path, line, error),
+        _ => anyhow!("script {}: {}", path, error),
+    }
+}
+
+fn create_engine(base: PathBuf, allow_write: bool) -> Engine {
+    // Rhai 本身没有文件系统和进程相关的功能, 只额外提供读取文件, 写入文件需要显式开启
+    let mut engine = Engine::new();
+    engine.set_max_operations(MAX_OPERATIONS);
+    engine.on_print(|text| info!("{}", text));
+    engine.on_debug(|text, _, _| debug!("{}", text));
+    let read_base = base.clone();
+    engine.register_fn(
+        "read_file",
+        move |path: &str| -> Result<String, Box<EvalAltResult>> {
+            fs::read_to_string(read_base.join(path))
+                .map_err(|e| format!("failed to read {}: {}", path, e).into())
+        },
+    );
+    engine.register_fn(
+        "write_file",
+        move |path: &str, content: &str| -> Result<(), Box<EvalAltResult>> {
+            if !allow_write {
+                return Err(format!(
+                    "writing {} is not allowed, use -clw-script-allow-write",
+                    path
+                )
+                .into());
+            }
+            fs::write(base.join(path), content)
+                .map_err(|e| format!("failed to write {}: {}", path, e).into())
+        },
+    );
+    engine
+}
+
+pub fn script_feature(path: String, config: &mut Configuration) -> Result<()> {
+    let content =
+        fs::read_to_string(&path).map_err(|e| anyhow!("failed to read script {}: {}", path, e))?;
+    let cwd = if config.work_dir.is_empty() {
+        env::current_dir()?.to_string_lossy().into_owned()
+    } else {
+        config.work_dir.clone()
+    };
+    let engine = create_engine(PathBuf::from(&cwd), config.script_allow_write);
+
+    let mut environment = Map::new();
+    for (key, value) in env::vars().chain(config.environment.iter().cloned()) {
+        environment.insert(key.into(), value.into());
+    }
+    let mut response_files = Map::new();
+    for (path, res) in config.response_map.iter() {
+        response_files.insert(path.into(), to_array(&res.values).into());
+    }
+    let mut scope = Scope::new();
+    scope.push("command", config.command.clone());
+    scope.push("args", to_array(&config.arguments));
+    scope.push("response_files", response_files);
+    scope.push("env", environment.clone());
+    scope.push("cwd", cwd.clone());
+    scope.push_constant("output", config.output_file().unwrap_or_default());
+
+    let ast = engine
+        .compile(&content)
+        .map_err(|e| script_error(&path, e.into()))?;
+    engine
+        .run_ast_with_scope(&mut scope, &ast)
+        .map_err(|e| script_error(&path, e))?;
+
+    let mut value = |name: &str| scope.remove::<Dynamic>(name).unwrap_or_default();
+    config.command = from_string("command", value("command"))?;
+    config.arguments = from_array("args", value("args"))?;
+    let response_files = value("response_files")
+        .try_cast::<Map>()
+        .ok_or_else(|| anyhow!("response_files must be a map"))?;
+    for (path, res) in config.response_map.iter_mut() {
+        if let Some(values) = response_files.get(path.as_str()) {
+            let values = from_array(&format!("response_files[\"{}\"]", path), values.clone())?;
+            if values != res.values {
+                res.values = values;
+                res.changed = true;
+            }
+        }
+    }
+    // 只记录修改, 新增和删除的环境变量
+    let new_environment = value("env")
+        .try_cast::<Map>()
+        .ok_or_else(|| anyhow!("env must be a map"))?;
+    for key in environment.keys() {
+        if !new_environment.contains_key(key) {
+            config.environment.retain(|(k, _)| k != key.as_str());
+            config.removed_environment.push(key.to_string());
+        }
+    }
+    for (key, v) in new_environment {
+        let v = from_string(&format!("env[\"{}\"]", key), v)?;
+        let old = environment.get(key.as_str()).cloned();
+        if old.and_then(|old| old.into_string().ok()).as_ref() != Some(&v) {
+            config.removed_environment.retain(|k| k != key.as_str());
+            config.environment.push((key.to_string(), v));
+        }
+    }
+    let new_cwd = from_string("cwd", value("cwd"))?;
+    if new_cwd != cwd {
+        config.work_dir = new_cwd;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_script_feature() {
+        let dir = env::temp_dir().join(format!("clw_script_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let script = dir.join("rewrite.rhai");
+        fs::write(
+            &script,
+            r#"
+if output.starts_with("tests/") {
+    args.push("-O0");
+}
+args.retain(|arg| arg != "-O2");
+env["CLW_SCRIPT_TEST"] = "1";
+"#,
+        )
+        .unwrap();
+
+        let mut config = Configuration::new();
+        config.command = "gcc".to_owned();
+        config.work_dir = dir.to_string_lossy().into_owned();
+        config.arguments = ["-O2", "-c", "a.c", "-o", "tests/a.o"]
+            .map(String::from)
+            .to_vec();
+        let path = script.to_string_lossy().into_owned();
+        script_feature(path.clone(), &mut config).unwrap();
+        assert_eq!(
+            config.arguments,
+            vec!["-c", "a.c", "-o", "tests/a.o", "-O0"]
+        );
+        assert_eq!(
+            config.environment,
+            vec![("CLW_SCRIPT_TEST".to_owned(), "1".to_owned())]
+        );
+
+        // 删除脚本之前设置的和进程本身的环境变量
+        env::set_var("CLW_SCRIPT_REMOVED", "1");
+        fs::write(
+            &script,
+            "env.remove(\"CLW_SCRIPT_TEST\");\nenv.remove(\"CLW_SCRIPT_REMOVED\");\n",
+        )
+        .unwrap();
+        script_feature(path.clone(), &mut config).unwrap();
+        assert!(config.environment.is_empty());
+        assert_eq!(
+            config.removed_environment,
+            vec!["CLW_SCRIPT_REMOVED", "CLW_SCRIPT_TEST"]
+        );
+
+        fs::write(&script, "let a = 1;\nwrite_file(\"out.txt\", \"x\");\n").unwrap();
+        let error = script_feature(path.clone(), &mut config)
+            .unwrap_err()
+            .to_string();
+        assert!(
+            error.starts_with(&format!("script {}:2:1:", path)),
+            "{}",
+            error
+        );
+        assert!(error.contains("-clw-script-allow-write"));
+        assert!(!dir.join("out.txt").exists());
+
+        config.script_allow_write = true;
+        script_feature(path.clone(), &mut config).unwrap();
+        assert_eq!(fs::read_to_string(dir.join("out.txt")).unwrap(), "x");
+
+        fs::write(&script, "args = 1;\n").unwrap();
+        assert!(script_feature(path, &mut config).is_err());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,97 @@
+use regex::Regex;
+use simplelog::info;
+
+// 根据命令的输出修改退出码
+pub enum ExitRule {
+    FailIfOutput(Regex),
+    SucceedIfOutput(Regex),
+}
+
+impl ExitRule {
+    pub fn describe(&self) -> String {
+        match self {
+            ExitRule::FailIfOutput(pattern) => format!(
+                "a zero exit code becomes 1 when stdout or stderr matches '{}'",
+                pattern
+            ),
+            ExitRule::SucceedIfOutput(pattern) => format!(
+                "a non-zero exit code becomes 0 when stdout or stderr matches '{}'",
+                pattern
+            ),
+        }
+    }
+}
+
+fn find_match(pattern: &Regex, stdout: &str, stderr: &str) -> Option<String> {
+    [("stdout", stdout), ("stderr", stderr)]
+        .into_iter()
+        .find_map(|(name, output)| {
+            // 输出匹配所在的整行
+            let m = pattern.find(output)?;
+            let start = output[..m.start()].rfind('\n').map_or(0, |i| i + 1);
+            let end = output[m.end()..]
+                .find('\n')
+                .map_or(output.len(), |i| m.end() + i);
+            let line = output[start..end].trim();
+            Some(format!("{} matches '{}': {}", name, pattern, line))
+        })
+}
+
+// 失败的规则优先, 同时匹配时命令仍然失败
+pub fn override_exit_code(rules: &[ExitRule], code: i32, stdout: &[u8], stderr: &[u8]) -> i32 {
+    let stdout = String::from_utf8_lossy(stdout);
+    let stderr = String::from_utf8_lossy(stderr);
+    let mut result = code;
+    if result != 0 {
+        let reason = rules.iter().find_map(|rule| match rule {
+            ExitRule::SucceedIfOutput(pattern) => find_match(pattern, &stdout, &stderr),
+            _ => None,
+        });
+        if let Some(reason) = reason {
+            info!("exit code {} -> 0: {}", result, reason);
+            result = 0;
+        }
+    }
+    if result == 0 {
+        let reason = rules.iter().find_map(|rule| match rule {
+            ExitRule::FailIfOutput(pattern) => find_match(pattern, &stdout, &stderr),
+            _ => None,
+        });
+        if let Some(reason) = reason {
+            info!("exit code {} -> 1: {}", result, reason);
+            result = 1;
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use regex::RegexBuilder;
+
+    fn pattern(p: &str) -> Regex {
+        RegexBuilder::new(p).multi_line(true).build().unwrap()
+    }
+
+    #[test]
+    fn test_override_exit_code() {
+        let rules = vec![
+            ExitRule::FailIfOutput(pattern("^error:")),
+            ExitRule::SucceedIfOutput(pattern("^warning: harmless")),
+        ];
+        assert_eq!(override_exit_code(&rules, 0, b"ok\n", b""), 0);
+        assert_eq!(override_exit_code(&rules, 0, b"ok\nerror: bad\n", b""), 1);
+        assert_eq!(override_exit_code(&rules, 0, b"", b"an error: x\n"), 0);
+        assert_eq!(
+            override_exit_code(&rules, 1, b"", b"warning: harmless\n"),
+            0
+        );
+        assert_eq!(override_exit_code(&rules, 2, b"", b"warning: other\n"), 2);
+        // 同时匹配时失败
+        assert_eq!(
+            override_exit_code(&rules, 1, b"error: bad\n", b"warning: harmless\n"),
+            1
+        );
+    }
+}
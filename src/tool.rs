@@ -0,0 +1,254 @@
+use std::env;
+use std::ffi::OsStr;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::time::UNIX_EPOCH;
+
+use crate::cache;
+use crate::report::tool_name;
+
+// 在 PATH 中查找命令, 包含路径分隔符的命令直接返回
+pub fn find_program(command: &str) -> Option<PathBuf> {
+    search_program(command, &env::var_os("PATH")?, None)
+}
+
+// 和 find_program 相同, 但是跳过 wrapper 自身, 避免安装为同名工具时重复调用自己
+pub fn find_real_program(command: &str) -> Option<PathBuf> {
+    let exe = env::current_exe().and_then(fs::canonicalize).ok();
+    search_program(command, &env::var_os("PATH")?, exe.as_deref())
+}
+
+fn search_program(command: &str, paths: &OsStr, skip: Option<&Path>) -> Option<PathBuf> {
+    let is_skipped =
+        |path: &Path| skip.is_some_and(|skip| fs::canonicalize(path).is_ok_and(|p| p == skip));
+    if command.contains(['/', '\\']) {
+        let path = PathBuf::from(command);
+        return if path.is_file() && !is_skipped(&path) {
+            Some(path)
+        } else {
+            None
+        };
+    }
+    for dir in env::split_paths(paths) {
+        for name in program_names(command) {
+            let path = dir.join(&name);
+            if path.is_file() && !is_skipped(&path) {
+                return Some(path);
+            }
+        }
+    }
+    None
+}
+
+fn program_names(command: &str) -> Vec<String> {
+    if cfg!(windows) && Path::new(command).extension().is_none() {
+        vec![command.to_string() + ".exe", command.to_string()]
+    } else {
+        vec![command.to_string()]
+    }
+}
+
+pub fn is_current_exe(command: &str) -> bool {
+    let exe = env::current_exe().and_then(fs::canonicalize);
+    let program = find_program(command).map(fs::canonicalize);
+    matches!((exe, program), (Ok(exe), Some(Ok(program))) if exe == program)
+}
+
+// 使用路径, 大小和修改时间标识一个工具, 工具升级后标识随之变化
+pub fn program_identity(command: &str) -> Option<String> {
+    let path = find_program(command)?;
+    let path = fs::canonicalize(&path).unwrap_or(path);
+    let metadata = fs::metadata(&path).ok()?;
+    let mtime = metadata
+        .modified()
+        .ok()
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map(|d| d.as_nanos())
+        .unwrap_or(0);
+    Some(format!(
+        "{}:{}:{}",
+        path.to_string_lossy(),
+        metadata.len(),
+        mtime
+    ))
+}
+
+// gcc/clang 等编译器驱动, 包括交叉编译前缀和版本后缀, 如 arm-linux-gnueabihf-gcc, clang-18
+pub fn is_compiler_driver(command: &str) -> bool {
+    let name = tool_name(command);
+    let name = name.trim_end_matches(|c: char| c.is_ascii_digit() || c == '.' || c == '-');
+    ["gcc", "g++", "cc", "c++", "clang", "clang++"]
+        .iter()
+        .any(|driver| name == *driver || name.ends_with(&format!("-{}", driver)))
+}
+
+// MSVC 风格的编译器驱动, 如 cl.exe, clang-cl
+pub fn is_msvc_driver(command: &str) -> bool {
+    let name = tool_name(&command.to_lowercase());
+    let name = name.trim_end_matches(|c: char| c.is_ascii_digit() || c == '.' || c == '-');
+    name == "cl" || name == "clang-cl" || name.ends_with("-clang-cl")
+}
+
+// GNU 风格的链接器, 如 ld, ld.bfd, ld.lld, arm-none-eabi-ld
+pub fn is_linker(command: &str) -> bool {
+    let name = tool_name(command);
+    let name = name.trim_end_matches(|c: char| c.is_ascii_digit() || c == '.' || c == '-');
+    [
+        "ld", "ld.bfd", "ld.gold", "ld.lld", "lld", "ld.mold", "mold",
+    ]
+    .iter()
+    .any(|linker| name == *linker || name.ends_with(&format!("-{}", linker)))
+}
+
+// 探测结果按照用户缓存, 共享临时目录中的固定文件可能被其它用户修改
+fn probe_cache_file() -> PathBuf {
+    let non_empty = |key: &str| env::var_os(key).filter(|value| !value.is_empty());
+    let dir = non_empty("XDG_CACHE_HOME")
+        .map(PathBuf::from)
+        .or_else(|| non_empty("LOCALAPPDATA").map(PathBuf::from))
+        .or_else(|| non_empty("HOME").map(|home| PathBuf::from(home).join(".cache")));
+    match dir {
+        Some(dir) => dir.join("cli-wrapper").join("probe-cache.txt"),
+        None => env::temp_dir().join(format!("clw-probe-cache-{}.txt", user_id())),
+    }
+}
+
+#[cfg(unix)]
+fn user_id() -> String {
+    unsafe { libc::getuid() }.to_string()
+}
+
+#[cfg(not(unix))]
+fn user_id() -> String {
+    env::var("USERNAME").unwrap_or_default()
+}
+
+// 探测编译器是否支持某个选项, 结果按照编译器标识缓存, 避免每次调用都执行探测
+pub fn supports_option(command: &str, option: &str) -> bool {
+    probe_option(command, option, &probe_cache_file())
+}
+
+fn probe_option(command: &str, option: &str, cache_file: &Path) -> bool {
+    // 标识包含编译器的路径, 大小和修改时间
+    let identity = match program_identity(command) {
+        Some(identity) => identity,
+        None => return false,
+    };
+    let mut content = fs::read_to_string(cache_file).unwrap_or_default();
+    for line in content.lines() {
+        let mut fields = line.split('\t');
+        if fields.next() == Some(identity.as_str()) && fields.next() == Some(option) {
+            return fields.next() == Some("1");
+        }
+    }
+
+    let supported = Command::new(command)
+        .args([option, "-E", "-x", "c", "-"])
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .map(|status| status.success())
+        .unwrap_or(false);
+    content += &format!(
+        "{}\t{}\t{}\n",
+        identity,
+        option,
+        if supported { 1 } else { 0 }
+    );
+    if let Some(dir) = cache_file.parent() {
+        fs::create_dir_all(dir).unwrap_or(());
+    }
+    cache::write_file(cache_file, content.as_bytes()).unwrap_or(());
+    supported
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_compiler_driver() {
+        assert!(is_compiler_driver("gcc"));
+        assert!(is_compiler_driver("/usr/bin/g++-12"));
+        assert!(is_compiler_driver("arm-linux-gnueabihf-gcc"));
+        assert!(is_compiler_driver("clang-18"));
+        assert!(is_compiler_driver("C:\\llvm\\bin\\clang++.exe"));
+        assert!(!is_compiler_driver("ld"));
+        assert!(!is_compiler_driver("ld.lld"));
+        assert!(!is_compiler_driver("gcc-ar"));
+        assert!(!is_compiler_driver("clang-cl"));
+        assert!(is_msvc_driver("C:\\VC\\bin\\CL.EXE"));
+        assert!(is_msvc_driver("clang-cl-18"));
+        assert!(!is_msvc_driver("clang"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_search_program() {
+        let root = env::temp_dir().join(format!("clw_search_{}", std::process::id()));
+        let (wrap, real) = (root.join("wrap"), root.join("real"));
+        fs::create_dir_all(&wrap).unwrap();
+        fs::create_dir_all(&real).unwrap();
+        let exe = fs::canonicalize(env::current_exe().unwrap()).unwrap();
+        std::os::unix::fs::symlink(&exe, wrap.join("clw-tool")).unwrap();
+        fs::write(real.join("clw-tool"), "").unwrap();
+
+        let paths = env::join_paths([&wrap, &real]).unwrap();
+        assert_eq!(
+            search_program("clw-tool", &paths, None),
+            Some(wrap.join("clw-tool"))
+        );
+        assert_eq!(
+            search_program("clw-tool", &paths, Some(&exe)),
+            Some(real.join("clw-tool"))
+        );
+        let paths = env::join_paths([&wrap]).unwrap();
+        assert_eq!(search_program("clw-tool", &paths, Some(&exe)), None);
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_probe_option() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let root = env::temp_dir().join(format!("clw_probe_{}", std::process::id()));
+        fs::create_dir_all(&root).unwrap();
+        let compiler = root.join("clw-cc");
+        let write_compiler = |option: &str| {
+            let script = format!("#!/bin/sh\n[ \"$1\" = \"{}\" ]\n", option);
+            fs::write(&compiler, script).unwrap();
+            fs::set_permissions(&compiler, fs::Permissions::from_mode(0o755)).unwrap();
+        };
+        write_compiler("-fgood");
+        let command = compiler.to_string_lossy().into_owned();
+        let cache_file = root.join("cache").join("probe-cache.txt");
+        assert!(probe_option(&command, "-fgood", &cache_file));
+        assert!(!probe_option(&command, "-fbad", &cache_file));
+        let content = fs::read_to_string(&cache_file).unwrap();
+        assert_eq!(content.lines().count(), 2);
+        assert!(content.starts_with(&program_identity(&command).unwrap()));
+
+        // 命中缓存时不再执行探测
+        fs::write(&cache_file, content.replace("-fgood\t1", "-fgood\t0")).unwrap();
+        assert!(!probe_option(&command, "-fgood", &cache_file));
+        // 编译器的大小和修改时间变化后重新探测
+        write_compiler("-fbad");
+        assert!(probe_option(&command, "-fbad", &cache_file));
+        assert_eq!(fs::read_dir(root.join("cache")).unwrap().count(), 1);
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_is_linker() {
+        assert!(is_linker("ld"));
+        assert!(is_linker("/usr/bin/ld.lld-18"));
+        assert!(is_linker("arm-none-eabi-ld.bfd"));
+        assert!(!is_linker("gold"));
+        assert!(!is_linker("ldd"));
+    }
+}
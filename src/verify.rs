@@ -0,0 +1,196 @@
+use anyhow::{bail, Result};
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::elf::Elf;
+use crate::link::library_search_dirs;
+use crate::Configuration;
+
+// 链接成功后对输出文件的检查
+pub enum Verification {
+    Needed(String),
+    NoNeeded(String),
+    NoUndefined,
+    // 空字符串表示不能有解释器, 即完全静态链接
+    Interpreter(String),
+}
+
+// libz.so 可以匹配 libz.so.1
+fn soname_matches(needed: &str, soname: &str) -> bool {
+    needed == soname
+        || needed
+            .strip_prefix(soname)
+            .is_some_and(|rest| rest.starts_with('.'))
+}
+
+// 依次在 DT_RUNPATH/DT_RPATH 和链接器的搜索路径中查找依赖库, 包括依赖库的依赖
+fn undefined_symbols(
+    config: &Configuration,
+    elf: &Elf,
+    output: &Path,
+    errors: &mut Vec<String>,
+) -> Result<Vec<String>> {
+    let dynamic = elf.dynamic()?;
+    let origin = output.parent().unwrap_or(Path::new("/"));
+    let mut dirs: Vec<PathBuf> = dynamic
+        .runpath
+        .as_ref()
+        .or(dynamic.rpath.as_ref())
+        .map(|paths| {
+            paths
+                .split(':')
+                .filter(|p| !p.is_empty())
+                .map(|p| {
+                    PathBuf::from(
+                        p.replace("${ORIGIN}", &origin.to_string_lossy())
+                            .replace("$ORIGIN", &origin.to_string_lossy()),
+                    )
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+    if !dynamic.needed.is_empty() {
+        dirs.extend(library_search_dirs(config));
+        dirs.extend(["/lib", "/usr/lib", "/lib64", "/usr/lib64"].map(PathBuf::from));
+    }
+
+    let mut defined = HashSet::new();
+    let mut visited = HashSet::new();
+    let mut pending = dynamic.needed.clone();
+    while let Some(soname) = pending.pop() {
+        if !visited.insert(soname.clone()) {
+            continue;
+        }
+        // 跳过其它架构的同名库
+        let found = dirs.iter().map(|dir| dir.join(&soname)).find_map(|path| {
+            let data = fs::read(&path).ok()?;
+            let library = Elf::parse(&data).ok()?;
+            if library.is_64() != elf.is_64() || library.machine().ok()? != elf.machine().ok()? {
+                return None;
+            }
+            let symbols = library.dynamic_symbols().ok()?;
+            Some((library.dynamic().ok()?.needed, symbols))
+        });
+        match found {
+            Some((needed, symbols)) => {
+                pending.extend(needed);
+                defined.extend(symbols.into_iter().filter(|s| s.defined).map(|s| s.name));
+            }
+            None => errors.push(format!("cannot find the needed library {}", soname)),
+        }
+    }
+
+    let symbols = if elf.has_dynamic_symbols() {
+        elf.dynamic_symbols()?
+    } else {
+        elf.symbols()?
+    };
+    let mut undefined: Vec<String> = symbols
+        .into_iter()
+        .filter(|s| !s.defined && !s.is_weak() && !defined.contains(&s.name))
+        .map(|s| s.name)
+        .collect();
+    undefined.sort();
+    undefined.dedup();
+    Ok(undefined)
+}
+
+pub fn verify_output(config: &Configuration) -> Result<()> {
+    let output = config.output_path()?;
+    let data = fs::read(&output)?;
+    let elf = Elf::parse(&data)?;
+    let needed = elf.dynamic()?.needed;
+
+    let mut errors = vec![];
+    for verification in config.verifications.iter() {
+        match verification {
+            Verification::Needed(soname) => {
+                if !needed.iter().any(|n| soname_matches(n, soname)) {
+                    errors.push(format!("{} is not in DT_NEEDED", soname));
+                }
+            }
+            Verification::NoNeeded(soname) => {
+                for n in needed.iter().filter(|n| soname_matches(n, soname)) {
+                    errors.push(format!("{} is in DT_NEEDED", n));
+                }
+            }
+            Verification::Interpreter(expected) => match elf.interpreter()? {
+                Some(actual) if expected.is_empty() => {
+                    errors.push(format!("unexpected interpreter {}", actual))
+                }
+                Some(actual) if &actual != expected => {
+                    errors.push(format!("interpreter is {}, expected {}", actual, expected))
+                }
+                None if !expected.is_empty() => {
+                    errors.push(format!("no interpreter, expected {}", expected))
+                }
+                _ => {}
+            },
+            Verification::NoUndefined => {
+                let undefined = undefined_symbols(config, &elf, &output, &mut errors)?;
+                if !undefined.is_empty() {
+                    errors.push(format!("undefined symbols: {}", undefined.join(", ")));
+                }
+            }
+        }
+    }
+    if !errors.is_empty() {
+        bail!(
+            "verification of {} failed:\n  {}",
+            output.to_string_lossy(),
+            errors.join("\n  ")
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::elf::tests::shared;
+    use std::env;
+
+    #[test]
+    fn test_verify_output() {
+        let dir = env::temp_dir().join(format!("clw_verify_{}", std::process::id()));
+        fs::create_dir_all(dir.join("lib")).unwrap();
+        fs::write(
+            dir.join("app"),
+            shared(
+                &["libfoo.so.1"],
+                &["main"],
+                &["foo", "missing"],
+                Some("/lib/ld.so"),
+            ),
+        )
+        .unwrap();
+        fs::write(
+            dir.join("lib/libfoo.so.1"),
+            shared(&[], &["foo"], &[], None),
+        )
+        .unwrap();
+
+        let mut config = Configuration::new();
+        config.command = "clw-test-missing-gcc".to_owned();
+        config.work_dir = dir.to_string_lossy().into_owned();
+        config.arguments = vec!["-o".to_owned(), "app".to_owned(), "-Llib".to_owned()];
+        config.verifications = vec![
+            Verification::Needed("libfoo.so".to_owned()),
+            Verification::Interpreter("/lib/ld.so".to_owned()),
+        ];
+        verify_output(&config).unwrap();
+
+        config.verifications = vec![
+            Verification::NoNeeded("libfoo.so".to_owned()),
+            Verification::Interpreter(String::new()),
+            Verification::NoUndefined,
+        ];
+        let error = verify_output(&config).unwrap_err().to_string();
+        assert!(error.contains("libfoo.so.1 is in DT_NEEDED"));
+        assert!(error.contains("unexpected interpreter /lib/ld.so"));
+        assert!(error.contains("undefined symbols: missing"));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
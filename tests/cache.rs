@@ -0,0 +1,68 @@
+#![cfg(unix)]
+
+use std::env;
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use std::process::Command;
+
+// 名称符合 gcc 的假编译器, -E 时输出源码, 否则写入 -o 并输出一个警告
+const FAKE_COMPILER: &str = r#"#!/bin/sh
+output=
+previous=
+for arg in "$@"; do
+    case "$arg" in
+        -E) cat a.c; exit 0 ;;
+    esac
+    if [ "$previous" = "-o" ]; then
+        output="$arg"
+    fi
+    previous="$arg"
+done
+echo "a.c:1:5: warning: unused variable 'x'" >&2
+echo object > "$output"
+"#;
+
+fn compile(dir: &Path, extra: &[&str]) -> i32 {
+    Command::new(env!("CARGO_BIN_EXE_cli-wrapper"))
+        .current_dir(dir)
+        .arg(dir.join("clw-fake-gcc"))
+        .arg(format!(
+            "-clw-cache-dir={}",
+            dir.join("cache").to_string_lossy()
+        ))
+        .args(extra)
+        .args(["-c", "a.c", "-o", "a.o"])
+        .output()
+        .unwrap()
+        .status
+        .code()
+        .unwrap()
+}
+
+#[test]
+fn test_cache_hit_applies_exit_rules() {
+    let dir = env::temp_dir().join(format!("clw_cache_{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let compiler = dir.join("clw-fake-gcc");
+    fs::write(&compiler, FAKE_COMPILER).unwrap();
+    fs::set_permissions(&compiler, fs::Permissions::from_mode(0o755)).unwrap();
+    fs::write(dir.join("a.c"), "int x;\n").unwrap();
+
+    // 第二次命中缓存, 退出码和输出文件应当和第一次相同
+    let rule = ["-clw-fail-if-output=warning"];
+    for _ in 0..2 {
+        assert_eq!(compile(&dir, &rule), 1);
+        fs::remove_file(dir.join("a.o")).unwrap_or(());
+    }
+    assert!(fs::read_to_string(dir.join("cache/stats"))
+        .unwrap()
+        .contains("hits 1\n"));
+    assert_eq!(compile(&dir, &[]), 0);
+    assert!(fs::read_to_string(dir.join("cache/stats"))
+        .unwrap()
+        .contains("hits 2\n"));
+    assert_eq!(fs::read_to_string(dir.join("a.o")).unwrap(), "object\n");
+
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,42 @@
+#![cfg(unix)]
+
+use std::env;
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use std::process::Command;
+
+fn run(program: &Path, args: &[&str]) -> String {
+    let output = Command::new(program).args(args).output().unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    String::from_utf8_lossy(&output.stdout).into_owned()
+}
+
+#[test]
+fn test_renamed_binary() {
+    let dir = env::temp_dir().join(format!("clw_multi_call_{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let exe = Path::new(env!("CARGO_BIN_EXE_cli-wrapper"));
+
+    // 改名后没有配置命令时, 和 cli-wrapper 一样使用第一个参数作为命令
+    let clw = dir.join("clw");
+    fs::copy(exe, &clw).unwrap();
+    assert_eq!(run(&clw, &["echo", "-c", "a.c"]), "-c a.c\n");
+    assert!(run(&clw, &["--version"]).starts_with("cli-wrapper "));
+
+    // 配置了命令时, 自身的选项也传给被包装的命令
+    let gcc = dir.join("gcc");
+    fs::copy(exe, &gcc).unwrap();
+    let real = dir.join("real-gcc");
+    fs::write(&real, "#!/bin/sh\nprintf '%s\\n' \"$*\"\n").unwrap();
+    fs::set_permissions(&real, fs::Permissions::from_mode(0o755)).unwrap();
+    fs::write(
+        dir.join("gcc-clw-config.txt"),
+        format!("-clw-command={}\n", real.to_string_lossy()),
+    )
+    .unwrap();
+    assert_eq!(run(&gcc, &["--version"]), "--version\n");
+    assert_eq!(run(&gcc, &["-h"]), "-h\n");
+
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,39 @@
+use std::env;
+use std::fs;
+
+use cli_wrapper::ResponseFile;
+
+#[test]
+fn test_response_file_api() {
+    let arguments = ["-c", "my file.c", "-DX=\"1\"", "-IC:\\include", "-o", "a.o"];
+    let content = arguments
+        .iter()
+        .map(|arg| ResponseFile::escape(arg))
+        .collect::<Vec<_>>()
+        .join(" ");
+    assert_eq!(ResponseFile::parse(&content), arguments);
+    assert_eq!(
+        ResponseFile::parse("-c \"a b.c\"\r\n  -o\ta.o\n"),
+        vec!["-c", "a b.c", "-o", "a.o"]
+    );
+
+    let dir = env::temp_dir().join(format!("clw_response_{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let original = dir.join("args.rsp").to_string_lossy().into_owned();
+    let new = dir.join("new.rsp").to_string_lossy().into_owned();
+    fs::write(&original, content).unwrap();
+    assert_eq!(ResponseFile::read(&original).unwrap(), arguments);
+    assert!(ResponseFile::read(&new).is_err());
+
+    let mut file = ResponseFile::new(original.clone(), new.clone());
+    file.remove_value("-DX=\"1\"");
+    file.replace_value("a.o", "b.o");
+    assert!(file.changed);
+    file.write().unwrap();
+    assert_eq!(
+        ResponseFile::read(&new).unwrap(),
+        vec!["-c", "my file.c", "-IC:\\include", "-o", "b.o"]
+    );
+
+    fs::remove_dir_all(&dir).unwrap();
+}